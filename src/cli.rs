@@ -0,0 +1,197 @@
+use crate::config::Config;
+use crate::db::IndexDb;
+use crate::indexer::{self, IndexProgress, PreviewCounts};
+use crate::media::MediaType;
+use anyhow::{anyhow, bail};
+use chrono::DateTime;
+use clap::{Parser, Subcommand};
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Gamepad-controlled media gallery. Runs the fullscreen UI if no command is given
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Config file path [default: $XDG_CONFIG_HOME/photoflow.toml]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Database file path [default: $XDG_DATA_HOME/photoflow.db]
+    #[arg(long, value_name = "PATH")]
+    pub db: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Commands that run without a display
+#[derive(Subcommand)]
+pub enum Command {
+    /// Update the index and print the progress
+    Index,
+    /// Print the index statistics
+    Stats,
+    /// Print the indexed files in the gallery order
+    List,
+    /// Check that the indexed files exist and their thumbnails can be decoded
+    Check,
+    /// Remove the entries of missing files and files that are not in the sources anymore
+    Prune,
+}
+
+pub fn run(command: Command, cfg_path: &Path, db_path: &Path) -> anyhow::Result<()> {
+    match command {
+        Command::Index => index(&Config::load(cfg_path)?, db_path),
+        Command::Stats => stats(db_path),
+        Command::List => list(db_path),
+        Command::Check => check(db_path),
+        Command::Prune => prune(&Config::load(cfg_path)?, db_path),
+    }
+}
+
+fn open_db(db_path: &Path) -> anyhow::Result<IndexDb> {
+    IndexDb::open(db_path)
+        .map_err(|e| anyhow!("Failed to open database file `{}`: {e}", db_path.display()))
+}
+
+/// Opens the database for the commands that only read the index
+fn open_indexed_db(db_path: &Path) -> anyhow::Result<IndexDb> {
+    let db = open_db(db_path)?;
+    if !db.has_order_table()? {
+        bail!("The media index is not built yet. Run the `index` command first");
+    }
+    Ok(db)
+}
+
+fn index(config: &Config, db_path: &Path) -> anyhow::Result<()> {
+    gstreamer::init()?;
+
+    let db = open_db(db_path)?;
+    let db = indexer::update_index(&config.sources, db, &PrintProgress::default())?;
+    println!("Indexed media files: {}", db.get_item_count()?);
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct PrintProgress {
+    total: AtomicUsize,
+    processed: AtomicUsize,
+}
+
+impl IndexProgress for PrintProgress {
+    fn start(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+        println!("Media files found: {total}");
+    }
+
+    fn file_processed(&self) {
+        let processed = self.processed.fetch_add(1, Ordering::Relaxed) + 1;
+        let total = self.total.load(Ordering::Relaxed);
+        if processed % 100 == 0 || processed == total {
+            println!("Processed {processed}/{total}");
+        }
+    }
+
+    fn finish(&self, previews: &PreviewCounts) {
+        println!("Loaded previews: {previews}");
+    }
+}
+
+fn stats(db_path: &Path) -> anyhow::Result<()> {
+    let db = open_indexed_db(db_path)?;
+    let stats = db.get_stats()?;
+
+    println!("Media files: {}", stats.total_count);
+    for (media_type, count) in &stats.type_counts {
+        println!("  {media_type}: {count}");
+    }
+    println!("Live Photos: {}", stats.live_photo_count);
+    println!("Motion photos: {}", stats.motion_photo_count);
+
+    if let (Some(min), Some(max)) = (stats.min_timestamp, stats.max_timestamp) {
+        let format = |ts: i64| {
+            DateTime::from_timestamp(ts, 0)
+                .map(|dt| dt.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        };
+        println!("Dates: {} - {}", format(min), format(max));
+    }
+
+    let size_mib = stats.thumbnails_size as f64 / (1024.0 * 1024.0);
+    println!("Thumbnails size: {size_mib:.1} MiB");
+
+    Ok(())
+}
+
+fn list(db_path: &Path) -> anyhow::Result<()> {
+    let db = open_indexed_db(db_path)?;
+
+    for entry in db.get_entries()? {
+        println!(
+            "{}\t{}\t{}",
+            entry.datetime.format("%Y-%m-%d %H:%M:%S %:z"),
+            entry.media_type.name(),
+            entry.path
+        );
+    }
+
+    Ok(())
+}
+
+fn check(db_path: &Path) -> anyhow::Result<()> {
+    let db = open_indexed_db(db_path)?;
+
+    let mut checked = 0;
+    let mut problems = 0;
+    db.for_each_thumbnail(|path, thumbnail| {
+        checked += 1;
+
+        if !Path::new(path).is_file() {
+            problems += 1;
+            println!("Missing file: {path}");
+        }
+
+        if let Err(e) = image::load_from_memory_with_format(thumbnail, ImageFormat::Jpeg) {
+            problems += 1;
+            println!("Broken thumbnail: {path}: {e}");
+        }
+    })?;
+
+    if problems > 0 {
+        bail!("Found {problems} problems in {checked} entries");
+    }
+
+    println!("All {checked} entries are fine");
+    Ok(())
+}
+
+fn prune(config: &Config, db_path: &Path) -> anyhow::Result<()> {
+    let mut db = open_db(db_path)?;
+
+    // RAW files hidden by their JPEG siblings are stale as well
+    let is_stale = |path: &Path| {
+        !path.is_file()
+            || !indexer::is_source_media(&config.sources, path)
+            || MediaType::detect(path).is_none()
+    };
+
+    let stale_paths = db
+        .get_paths()?
+        .into_iter()
+        .filter(|path| is_stale(Path::new(path)))
+        .collect::<Vec<_>>();
+
+    for path in &stale_paths {
+        println!("Removing: {path}");
+    }
+
+    let removed = db.remove_paths(&stale_paths)?;
+    indexer::pair_live_photos(&mut db)?;
+    db.rebuild_order_table()?;
+    db.vacuum()?;
+
+    println!("Removed entries: {removed}");
+    Ok(())
+}
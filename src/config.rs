@@ -1,6 +1,349 @@
+use anyhow::anyhow;
+use chrono::TimeDelta;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
 use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub sources: Vec<String>,
+    pub sources: Vec<Source>,
+    /// Input bindings by action names
+    #[serde(default)]
+    pub controls: BTreeMap<String, ControlBinding>,
+    #[serde(default)]
+    pub gallery: GalleryConfig,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Config> {
+        let config = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file `{}`: {e}", path.display()))?;
+        toml::from_str::<Config>(&config)
+            .map_err(|e| anyhow!("Failed to parse config file `{}`: {e}", path.display()))
+    }
+}
+
+/// Gallery screen settings
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GalleryConfig {
+    /// Number of screens of thumbnails loaded ahead in the scroll direction
+    pub prefetch_screens: usize,
+    pub grouping: TimelineGrouping,
+}
+
+impl Default for GalleryConfig {
+    fn default() -> Self {
+        Self {
+            prefetch_screens: 2,
+            grouping: TimelineGrouping::Month,
+        }
+    }
+}
+
+/// Period of the capture time that groups the gallery items under a date header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelineGrouping {
+    Day,
+    Month,
+    Year,
+}
+
+/// Inputs bound to an action. A missing list keeps the default bindings of the action
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ControlBinding {
+    pub gamepad: Option<Vec<String>>,
+    pub keyboard: Option<Vec<String>>,
+}
+
+/// A media directory. It is configured either with a plain path or with a table
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    pub label: String,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    /// Number of nested directory levels to scan. Zero means only the source directory itself
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    pub skip_hidden: bool,
+    /// Added to the capture time written by the camera to fix its clock
+    pub clock_offset: TimeDelta,
+    /// Skip RAW files that have a JPEG sibling with the same name
+    pub hide_raw_with_jpeg: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceTable {
+    path: PathBuf,
+    label: Option<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    max_depth: Option<usize>,
+    #[serde(default)]
+    follow_symlinks: bool,
+    #[serde(default = "default_skip_hidden")]
+    skip_hidden: bool,
+    clock_offset: Option<String>,
+    #[serde(default)]
+    hide_raw_with_jpeg: bool,
+}
+
+fn default_skip_hidden() -> bool {
+    true
+}
+
+impl Source {
+    /// Checks a file found inside the source against its depth limit and glob patterns.
+    /// Hidden files are always skipped
+    pub fn accepts(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.path) else {
+            return false;
+        };
+
+        let is_visible = relative.file_name().is_some_and(|name| !is_hidden(name));
+        let is_dir_accepted = relative
+            .parent()
+            .is_some_and(|dir| self.accepts_relative_dir(dir));
+        if !is_visible || !is_dir_accepted {
+            return false;
+        }
+
+        let included = self.include.as_ref().is_none_or(|g| g.is_match(relative));
+        included && !self.exclude.is_match(relative)
+    }
+
+    /// Checks if a directory inside the source should be scanned
+    pub fn accepts_dir(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.path)
+            .is_ok_and(|relative| self.accepts_relative_dir(relative))
+    }
+
+    fn accepts_relative_dir(&self, relative: &Path) -> bool {
+        let depth = relative.components().count();
+        if self.max_depth.is_some_and(|max_depth| depth > max_depth) {
+            return false;
+        }
+
+        !self.skip_hidden || !relative.components().any(|c| is_hidden(c.as_os_str()))
+    }
+
+    fn from_path(path: PathBuf) -> Self {
+        Self {
+            label: path.to_string_lossy().into_owned(),
+            path,
+            include: None,
+            exclude: GlobSet::empty(),
+            max_depth: None,
+            follow_symlinks: false,
+            skip_hidden: true,
+            clock_offset: TimeDelta::zero(),
+            hide_raw_with_jpeg: false,
+        }
+    }
+
+    fn from_table(table: SourceTable) -> Result<Self, String> {
+        let include = if table.include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(&table.include)?)
+        };
+
+        let clock_offset = match &table.clock_offset {
+            Some(offset) => parse_clock_offset(offset)?,
+            None => TimeDelta::zero(),
+        };
+
+        Ok(Self {
+            label: table
+                .label
+                .unwrap_or_else(|| table.path.to_string_lossy().into_owned()),
+            path: table.path,
+            include,
+            exclude: build_glob_set(&table.exclude)?,
+            max_depth: table.max_depth,
+            follow_symlinks: table.follow_symlinks,
+            skip_hidden: table.skip_hidden,
+            clock_offset,
+            hide_raw_with_jpeg: table.hide_raw_with_jpeg,
+        })
+    }
+}
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| name.starts_with('.'))
+}
+
+/// Patterns are case-insensitive like the file extensions
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| e.to_string())?;
+        builder.add(glob);
+    }
+
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Parses an offset in the `[+|-]HH:MM[:SS]` format
+fn parse_clock_offset(value: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("invalid clock offset `{value}`, expected `[+|-]HH:MM[:SS]`");
+
+    let (sign, hms) = match value.strip_prefix('-') {
+        Some(hms) => (-1, hms),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let parts = hms
+        .split(':')
+        .map(|part| part.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (hours, minutes, seconds) = match parts[..] {
+        [h, m] if m < 60 => (h, m, 0),
+        [h, m, s] if m < 60 && s < 60 => (h, m, s),
+        _ => return Err(invalid()),
+    };
+
+    let seconds = i64::from(hours) * 3600 + i64::from(minutes) * 60 + i64::from(seconds);
+    Ok(TimeDelta::seconds(sign * seconds))
+}
+
+impl<'de> de::Deserialize<'de> for Source {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(SourceVisitor)
+    }
+}
+
+struct SourceVisitor;
+
+impl<'de> Visitor<'de> for SourceVisitor {
+    type Value = Source;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a directory path or a source table")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Source, E> {
+        Ok(Source::from_path(value.into()))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Source, A::Error> {
+        let table: SourceTable = de::Deserialize::deserialize(MapAccessDeserializer::new(map))?;
+        Source::from_table(table).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_sources(sources: &str) -> Result<Vec<Source>, String> {
+        toml::from_str::<Config>(&format!("sources = {sources}"))
+            .map(|config| config.sources)
+            .map_err(|e| e.to_string())
+    }
+
+    fn parse_source(source: &str) -> Source {
+        parse_sources(&format!("[{source}]")).unwrap().remove(0)
+    }
+
+    fn accepts(source: &Source, path: &str) -> bool {
+        source.accepts(&source.path.join(path))
+    }
+
+    #[test]
+    fn parses_plain_path() {
+        let sources = parse_sources(r#"["/media/photos", { path = "/media/videos" }]"#).unwrap();
+
+        for source in &sources {
+            assert_eq!(source.label, source.path.to_string_lossy());
+            assert_eq!(source.max_depth, None);
+            assert!(!source.follow_symlinks);
+            assert!(source.skip_hidden);
+            assert_eq!(source.clock_offset, TimeDelta::zero());
+            assert!(!source.hide_raw_with_jpeg);
+        }
+        assert_eq!(sources[0].path, Path::new("/media/photos"));
+        assert!(accepts(&sources[0], "2024/01/IMG_1.JPG"));
+        assert!(!accepts(&sources[0], "2024/.thumbnails/IMG_1.JPG"));
+        assert!(!accepts(&sources[0], "2024/.IMG_1.JPG"));
+        assert!(!sources[0].accepts(Path::new("/media/videos/VID_1.mp4")));
+    }
+
+    #[test]
+    fn limits_depth() {
+        let source = parse_source(r#"{ path = "/media", max_depth = 1 }"#);
+
+        assert!(accepts(&source, "IMG_1.JPG"));
+        assert!(accepts(&source, "2024/IMG_1.JPG"));
+        assert!(!accepts(&source, "2024/01/IMG_1.JPG"));
+        assert!(source.accepts_dir(Path::new("/media/2024")));
+        assert!(!source.accepts_dir(Path::new("/media/2024/01")));
+
+        let source = parse_source(r#"{ path = "/media", max_depth = 0 }"#);
+        assert!(accepts(&source, "IMG_1.JPG"));
+        assert!(!accepts(&source, "2024/IMG_1.JPG"));
+    }
+
+    #[test]
+    fn excludes_included_files() {
+        let source = parse_source(
+            r#"{ path = "/media", include = ["**/*.jpg", "*.mp4"], exclude = ["tmp/**"] }"#,
+        );
+
+        assert!(accepts(&source, "2024/IMG_1.JPG"));
+        assert!(accepts(&source, "VID_1.mp4"));
+        assert!(!accepts(&source, "2024/IMG_1.heic"));
+        assert!(!accepts(&source, "tmp/IMG_1.jpg"));
+
+        let source = parse_source(r#"{ path = "/media", exclude = ["*.mov"] }"#);
+        assert!(accepts(&source, "IMG_1.heic"));
+        assert!(!accepts(&source, "IMG_1.MOV"));
+    }
+
+    #[test]
+    fn walks_hidden_directories() {
+        let source = parse_source(r#"{ path = "/media", skip_hidden = false }"#);
+
+        assert!(accepts(&source, ".photos/IMG_1.JPG"));
+        assert!(source.accepts_dir(Path::new("/media/.photos")));
+        // Hidden files are skipped anyway
+        assert!(!accepts(&source, ".photos/.IMG_1.JPG"));
+    }
+
+    #[test]
+    fn parses_clock_offsets() {
+        let offset = |value: &str| {
+            parse_sources(&format!(
+                r#"[{{ path = "/media", clock_offset = "{value}" }}]"#
+            ))
+            .map(|mut sources| sources.remove(0).clock_offset)
+        };
+
+        assert_eq!(offset("01:30"), Ok(TimeDelta::minutes(90)));
+        assert_eq!(offset("+25:00:05"), Ok(TimeDelta::seconds(90005)));
+        assert_eq!(offset("-00:00:30"), Ok(TimeDelta::seconds(-30)));
+
+        for value in [
+            "", "1", "1:2:3:4", "1:60", "1:00:60", "+-1:00", "1h:00", "-1:-5",
+        ] {
+            let error = offset(value).unwrap_err();
+            assert!(error.contains("invalid clock offset"), "{value}: {error}");
+        }
+    }
 }
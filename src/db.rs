@@ -1,36 +1,132 @@
-use rusqlite::Connection;
+use crate::config::TimelineGrouping;
+use crate::exif_orientation::ExifOrientation;
+use crate::media::{EmbeddedVideo, MediaMetadata, MediaType};
+use anyhow::bail;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use rusqlite::types::{FromSqlError, Type};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Row, Transaction, params};
+use std::collections::HashMap;
 use std::path::Path;
 
+/// Ordered schema migrations. The migration at index `i` upgrades the database
+/// from `user_version = i` to `user_version = i + 1`. Existing steps must never be changed,
+/// new steps are only appended to the end of the list.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema (databases created before versioning have `user_version = 0`
+    // and may already contain the table)
+    "CREATE TABLE IF NOT EXISTS media (
+        path TEXT PRIMARY KEY,
+        finfo TEXT, -- file size and mtime (used for changes detection)
+        timestamp INTEGER,
+        metadata INTEGER, -- exif_orientation for images / duration_ms for videos
+        is_valid INTEGER,
+        thumbnail BLOB
+    );",
+    // 2: replace the overloaded `metadata` column with typed media columns
+    "ALTER TABLE media ADD COLUMN media_type TEXT;
+    ALTER TABLE media ADD COLUMN utc_offset INTEGER; -- original timestamp offset from UTC (seconds)
+    ALTER TABLE media ADD COLUMN width INTEGER;
+    ALTER TABLE media ADD COLUMN height INTEGER;
+    ALTER TABLE media ADD COLUMN orientation INTEGER; -- exif orientation (images only)
+    ALTER TABLE media ADD COLUMN duration_ms INTEGER; -- videos only
+    ALTER TABLE media ADD COLUMN camera_make TEXT;
+    ALTER TABLE media ADD COLUMN camera_model TEXT;
+    UPDATE media SET media_type = CASE
+        WHEN path LIKE '%.jpg' OR path LIKE '%.jpeg' THEN 'jpeg'
+        WHEN path LIKE '%.heic' THEN 'heic'
+        WHEN path LIKE '%.mp4' THEN 'mp4'
+        WHEN path LIKE '%.mov' THEN 'mov'
+    END;
+    DELETE FROM media WHERE media_type IS NULL;
+    UPDATE media SET orientation = metadata WHERE media_type IN ('jpeg', 'heic');
+    UPDATE media SET duration_ms = metadata WHERE media_type IN ('mp4', 'mov');
+    -- only the rows without the old metadata are reindexed, the columns that can't be derived
+    -- from it (size, camera, UTC offset) stay empty until the file changes
+    UPDATE media SET finfo = '' WHERE timestamp IS NULL OR metadata IS NULL;
+    ALTER TABLE media DROP COLUMN metadata;",
+    // 3: Live Photo pairing (files that may have a content identifier are reindexed)
+    "ALTER TABLE media ADD COLUMN content_id TEXT;
+    ALTER TABLE media ADD COLUMN live_video TEXT; -- path of the paired motion part (stills only)
+    UPDATE media SET finfo = '' WHERE media_type IN ('jpeg', 'heic', 'mov');",
+    // 4: video embedded into a motion photo (JPEG files are reindexed to find it)
+    "ALTER TABLE media ADD COLUMN motion_offset INTEGER;
+    ALTER TABLE media ADD COLUMN motion_length INTEGER;
+    UPDATE media SET finfo = '' WHERE media_type = 'jpeg';",
+    // 5: sort orders (the new columns are filled for unchanged files on the next indexing)
+    "CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT);
+    ALTER TABLE media ADD COLUMN file_mtime INTEGER; -- file modification time (unix seconds)
+    ALTER TABLE media ADD COLUMN source TEXT; -- label of the source directory",
+];
+
+/// File name part of the media path
+const FILE_NAME: &str = "replace(path, rtrim(path, replace(path, '/', '')), '')";
+
+const SORT_ORDER_KEY: &str = "sort_order";
+
+const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
 pub struct IndexDb {
     conn: Connection,
 }
 
 impl IndexDb {
-    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<IndexDb> {
-        let client = IndexDb {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<IndexDb> {
+        let mut client = IndexDb {
             conn: Connection::open(path)?,
         };
 
-        client.conn.pragma_update(None, "synchronous", "OFF")?;
-        client.conn.pragma_update(None, "journal_mode", "OFF")?;
+        // WAL keeps the database consistent if the process is interrupted in the middle of a write.
+        // `NORMAL` synchronisation is durable enough in WAL mode and much faster than `FULL`
+        client.conn.pragma_update(None, "journal_mode", "WAL")?;
+        client.conn.pragma_update(None, "synchronous", "NORMAL")?;
+
+        client.migrate()?;
 
         Ok(client)
     }
 
-    pub fn create_index_if_not_exists(&self) -> rusqlite::Result<()> {
+    /// Opens an additional connection that is used only for reading
+    /// and does not block the indexing writer (requires the database to be opened with `open` first)
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> anyhow::Result<IndexDb> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let client = IndexDb {
+            conn: Connection::open_with_flags(path, flags)?,
+        };
+
+        let version = client.schema_version()?;
+        if version != SCHEMA_VERSION {
+            bail!("Unexpected database schema version {version} (expected {SCHEMA_VERSION})");
+        }
+
+        Ok(client)
+    }
+
+    pub fn schema_version(&self) -> rusqlite::Result<i64> {
         self.conn
-            .execute(
-                "CREATE TABLE IF NOT EXISTS media (
-                    path TEXT PRIMARY KEY,
-                    finfo TEXT, -- file size and mtime (used for changes detection)
-                    timestamp INTEGER,
-                    metadata INTEGER, -- exif_orientation for images / duration_ms for videos
-                    is_valid INTEGER,
-                    thumbnail BLOB
-                )",
-                (),
-            )
-            .map(|_| ())
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+    }
+
+    fn migrate(&mut self) -> anyhow::Result<()> {
+        let version = self.schema_version()?;
+
+        if version > SCHEMA_VERSION {
+            bail!(
+                "Database schema version {version} is newer than the supported version {SCHEMA_VERSION}. \
+                Please update the application"
+            );
+        }
+
+        for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let target_version = idx as i64 + 1;
+            log::info!("Migrating database schema to version {target_version}...");
+
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", target_version)?;
+            tx.commit()?;
+        }
+
+        Ok(())
     }
 
     pub fn invalidate_index(&self) -> rusqlite::Result<()> {
@@ -45,61 +141,174 @@ impl IndexDb {
             .map(|_| ())
     }
 
-    pub fn rebuild_order_table(&self) -> rusqlite::Result<()> {
-        self.conn.execute("DROP TABLE IF EXISTS media_order", ())?;
-        self.conn
-            .execute("CREATE TABLE media_order (id INTEGER UNIQUE)", ())?;
-        self.conn
-            .execute(
-                "INSERT INTO media_order (id) SELECT rowid FROM media ORDER BY timestamp",
-                (),
-            )
-            .map(|_| ())
+    /// Rebuilds the order table with the stored sort order in a single transaction,
+    /// so readers never observe a missing or partially filled table
+    pub fn rebuild_order_table(&mut self) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        let order = get_sort_order(&tx)?;
+        fill_order_table(&tx, order)?;
+        tx.commit()
     }
 
-    pub fn set_valid_if_unchanged(&self, path: &str, finfo: &str) -> rusqlite::Result<bool> {
-        self.conn
-            .execute(
-                "UPDATE media SET is_valid = 1 WHERE path = ?1 AND finfo = ?2",
-                (path, finfo),
-            )
-            .map(|count| count == 1)
+    pub fn get_sort_order(&self) -> rusqlite::Result<SortOrder> {
+        get_sort_order(&self.conn)
+    }
+
+    /// Stores the sort order and rebuilds the order table with it in a single transaction
+    pub fn set_sort_order(&mut self, order: SortOrder) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            [SORT_ORDER_KEY, order.name()],
+        )?;
+        fill_order_table(&tx, order)?;
+        tx.commit()
+    }
+
+    /// Returns the entries that can be a part of a Live Photo
+    pub fn get_live_photo_candidates(&self) -> rusqlite::Result<Vec<LivePhotoCandidate>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, media_type, content_id, duration_ms, timestamp FROM media \
+            WHERE media_type IN ('jpeg', 'heic', 'mov')",
+        )?;
+        let rows = stmt.query_map((), |row| {
+            let media_type: String = row.get(1)?;
+            let duration_ms: Option<i64> = row.get(3)?;
+            Ok(LivePhotoCandidate {
+                path: row.get(0)?,
+                is_video: media_type == "mov",
+                content_id: row.get(2)?,
+                duration_ms: duration_ms.map(|d| d as u64),
+                timestamp: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Replaces all Live Photo links with the `(still, video)` path pairs in a single transaction
+    pub fn set_live_photo_pairs(&mut self, pairs: &[(String, String)]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("UPDATE media SET live_video = NULL", ())?;
+        {
+            let mut stmt = tx.prepare_cached("UPDATE media SET live_video = ?2 WHERE path = ?1")?;
+            for (still, video) in pairs {
+                stmt.execute([still, video])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Returns the file info of all indexed files (used for changes detection)
+    pub fn get_finfo_map(&self) -> rusqlite::Result<HashMap<String, String>> {
+        let mut stmt = self.conn.prepare("SELECT path, finfo FROM media")?;
+        let rows = stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
     }
 
-    pub fn upsert_entry(&self, e: &InsertionEntry) -> rusqlite::Result<()> {
+    pub fn get_finfo(&self, path: &str) -> rusqlite::Result<Option<String>> {
         self.conn
-            .execute(
-                "INSERT INTO media
-                    (path, finfo, timestamp, metadata, is_valid, thumbnail)
-                VALUES
-                    (?1, ?2, ?3, ?4, 1, ?5)
-                ON CONFLICT(path) DO UPDATE SET
-                    finfo = excluded.finfo,
-                    timestamp = excluded.timestamp,
-                    metadata = excluded.metadata,
-                    is_valid = excluded.is_valid,
-                    thumbnail = excluded.thumbnail",
-                (e.path, e.finfo, e.timestamp, e.metadata, e.thumbnail),
-            )
-            .map(|_| ())
+            .query_row("SELECT finfo FROM media WHERE path = ?1", [path], |row| {
+                row.get(0)
+            })
+            .optional()
+    }
+
+    /// Removes the entry of the file or the entries of all files in the directory
+    pub fn remove_entries(&self, path: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "DELETE FROM media WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'",
+            [path],
+        )
+    }
+
+    pub fn get_paths(&self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT path FROM media")?;
+        let rows = stmt.query_map((), |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Removes the entries of the files in a single transaction
+    pub fn remove_paths(&mut self, paths: &[String]) -> rusqlite::Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut removed = 0;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM media WHERE path = ?1")?;
+            for path in paths {
+                removed += stmt.execute([path])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Shrinks the database file after removing entries
+    pub fn vacuum(&self) -> rusqlite::Result<()> {
+        self.conn.execute("VACUUM", ()).map(|_| ())
+    }
+
+    /// Marks unchanged files as valid and writes updated entries in a single transaction
+    pub fn write_batch(
+        &mut self,
+        valid_entries: &[ValidEntry],
+        entries: &[InsertionEntry],
+    ) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "UPDATE media SET is_valid = 1, file_mtime = ?2, source = ?3 WHERE path = ?1",
+            )?;
+            for e in valid_entries {
+                stmt.execute((&e.path, e.file_mtime, &e.source))?;
+            }
+        }
+        for e in entries {
+            upsert_entry(&tx, e)?;
+        }
+        tx.commit()
+    }
+
+    pub fn get_entry(&self, index: i64) -> rusqlite::Result<MediaEntry> {
+        self.conn.query_row(
+            &format!("SELECT {MEDIA_ENTRY_COLUMNS} FROM media WHERE rowid=(SELECT id FROM media_order WHERE rowid=?1)"),
+            [index + 1],
+            MediaEntry::from_row,
+        )
     }
 
-    pub fn get_path_metadata_and_thumbnail(
-        &self,
-        index: i64,
-    ) -> rusqlite::Result<(String, i64, Vec<u8>)> {
+    pub fn get_thumbnail(&self, index: i64) -> rusqlite::Result<Vec<u8>> {
         self.conn.query_row(
-            "SELECT path, metadata, thumbnail FROM media WHERE rowid=(SELECT id FROM media_order WHERE rowid=?1)",
+            "SELECT thumbnail FROM media WHERE rowid=(SELECT id FROM media_order WHERE rowid=?1)",
             [index + 1],
-            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            |row| row.get(0),
         )
     }
 
-    pub fn get_path_and_metadata(&self, index: i64) -> rusqlite::Result<(String, i64)> {
+    /// Returns the media rowid that stays the same between order table rebuilds
+    pub fn get_media_id(&self, index: i64) -> rusqlite::Result<i64> {
         self.conn.query_row(
-            "SELECT path, metadata FROM media WHERE rowid=(SELECT id FROM media_order WHERE rowid=?1)",
+            "SELECT id FROM media_order WHERE rowid=?1",
             [index + 1],
-            |row| Ok((row.get(0)?, row.get(1)?)),
+            |row| row.get(0),
+        )
+    }
+
+    pub fn get_index_of_media(&self, id: i64) -> rusqlite::Result<Option<i64>> {
+        self.conn
+            .query_row(
+                "SELECT rowid - 1 FROM media_order WHERE id=?1",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Returns `true` if the order table is built (the index was updated at least once)
+    pub fn has_order_table(&self) -> rusqlite::Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'media_order')",
+            (),
+            |row| row.get(0),
         )
     }
 
@@ -107,12 +316,450 @@ impl IndexDb {
         self.conn
             .query_row("SELECT COUNT(id) FROM media_order", (), |row| row.get(0))
     }
+
+    /// Returns the consecutive runs of media that have the same group key in the gallery order.
+    /// The key depends on the sort order: the period of the local capture time or the modification
+    /// time, the source with the capture period or the first letter of the file name
+    pub fn get_groups(&self, grouping: TimelineGrouping) -> rusqlite::Result<Vec<MediaGroup>> {
+        let period_format = match grouping {
+            TimelineGrouping::Day => "%Y-%m-%d",
+            TimelineGrouping::Month => "%Y-%m",
+            TimelineGrouping::Year => "%Y",
+        };
+
+        let order = self.get_sort_order()?;
+        let capture_time = "media.timestamp + IFNULL(media.utc_offset, 0)";
+        let modification_time = "CAST(strftime('%s', IFNULL(media.file_mtime, media.timestamp), \
+            'unixepoch', 'localtime') AS INTEGER)";
+        let period = format!("strftime('{period_format}', local_time, 'unixepoch')");
+        let source_period = format!("name || '|' || {period}");
+        let first_letter = format!("upper(substr({FILE_NAME}, 1, 1))");
+
+        let (local_time, name, key) = match order {
+            SortOrder::NewestFirst | SortOrder::OldestFirst => {
+                (capture_time, "NULL", period.as_str())
+            }
+            SortOrder::FileModified => (modification_time, "NULL", period.as_str()),
+            SortOrder::SourceThenDate => (
+                capture_time,
+                "IFNULL(media.source, '')",
+                source_period.as_str(),
+            ),
+            SortOrder::FileName => (capture_time, first_letter.as_str(), "name"),
+        };
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT idx, local_time, name FROM ( \
+                SELECT idx, local_time, name, {key} AS key, LAG({key}) OVER (ORDER BY idx) AS prev_key \
+                FROM ( \
+                    SELECT media_order.rowid - 1 AS idx, {local_time} AS local_time, {name} AS name \
+                    FROM media_order JOIN media ON media.rowid = media_order.id)) \
+            WHERE prev_key IS NOT key ORDER BY idx"
+        ))?;
+
+        let starts = stmt
+            .query_map((), |row| {
+                let idx: i64 = row.get(0)?;
+                let local_time: i64 = row.get(1)?;
+                let name: Option<String> = row.get(2)?;
+                Ok((idx as usize, local_time, name))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let item_count = self.get_item_count()? as usize;
+        let ends = starts
+            .iter()
+            .skip(1)
+            .map(|(idx, _, _)| *idx)
+            .chain([item_count])
+            .collect::<Vec<_>>();
+
+        let groups = starts
+            .into_iter()
+            .zip(ends)
+            .map(|((offset, local_time, name), end)| {
+                let date = DateTime::from_timestamp(local_time, 0)
+                    .unwrap_or_default()
+                    .date_naive();
+                let name = name.unwrap_or_default();
+
+                MediaGroup {
+                    offset,
+                    count: end - offset,
+                    key: match order {
+                        SortOrder::FileName => GroupKey::Letter(name),
+                        SortOrder::SourceThenDate => GroupKey::SourceDate(name, date),
+                        _ => GroupKey::Date(date),
+                    },
+                }
+            })
+            .collect();
+
+        Ok(groups)
+    }
+
+    /// Returns all entries in the gallery order
+    pub fn get_entries(&self) -> rusqlite::Result<Vec<MediaEntry>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {MEDIA_ENTRY_COLUMNS} FROM media_order \
+            JOIN media ON media.rowid = media_order.id ORDER BY media_order.rowid"
+        ))?;
+        let rows = stmt.query_map((), MediaEntry::from_row)?;
+        rows.collect()
+    }
+
+    /// Calls `f` with the path and the thumbnail of each entry without loading all thumbnails at once
+    pub fn for_each_thumbnail(&self, mut f: impl FnMut(&str, &[u8])) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.prepare("SELECT path, thumbnail FROM media")?;
+        let mut rows = stmt.query(())?;
+        while let Some(row) = rows.next()? {
+            let path = row.get_ref(0)?.as_str()?;
+            let thumbnail = row.get_ref(1)?.as_blob()?;
+            f(path, thumbnail);
+        }
+
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> rusqlite::Result<IndexStats> {
+        let mut stmt = self.conn.prepare(
+            "SELECT media_type, COUNT(*) FROM media GROUP BY media_type ORDER BY COUNT(*) DESC",
+        )?;
+        let rows = stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let type_counts = rows.collect::<rusqlite::Result<_>>()?;
+
+        self.conn.query_row(
+            "SELECT COUNT(*), MIN(timestamp), MAX(timestamp), SUM(length(thumbnail)), COUNT(live_video), \
+            COUNT(motion_offset) FROM media",
+            (),
+            |row| {
+                Ok(IndexStats {
+                    total_count: row.get(0)?,
+                    type_counts,
+                    min_timestamp: row.get(1)?,
+                    max_timestamp: row.get(2)?,
+                    thumbnails_size: row.get::<_, Option<i64>>(3)?.unwrap_or_default(),
+                    live_photo_count: row.get(4)?,
+                    motion_photo_count: row.get(5)?,
+                })
+            },
+        )
+    }
+}
+
+/// Motion parts of Live Photos are represented by their stills
+fn fill_order_table(tx: &Transaction, order: SortOrder) -> rusqlite::Result<()> {
+    let order_by = match order {
+        SortOrder::NewestFirst => "timestamp DESC, path".to_string(),
+        SortOrder::OldestFirst => "timestamp, path".to_string(),
+        SortOrder::FileName => format!("{FILE_NAME} COLLATE NOCASE, timestamp"),
+        SortOrder::SourceThenDate => "source, timestamp DESC, path".to_string(),
+        SortOrder::FileModified => "IFNULL(file_mtime, timestamp) DESC, path".to_string(),
+    };
+
+    tx.execute("DROP TABLE IF EXISTS media_order", ())?;
+    tx.execute("CREATE TABLE media_order (id INTEGER UNIQUE)", ())?;
+    tx.execute(
+        &format!(
+            "INSERT INTO media_order (id) SELECT rowid FROM media \
+            WHERE path NOT IN (SELECT live_video FROM media WHERE live_video IS NOT NULL) \
+            ORDER BY {order_by}"
+        ),
+        (),
+    )?;
+
+    Ok(())
+}
+
+/// Unknown or missing values fall back to the default order
+fn get_sort_order(conn: &Connection) -> rusqlite::Result<SortOrder> {
+    let name: Option<String> = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            [SORT_ORDER_KEY],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(name
+        .and_then(|name| SortOrder::from_name(&name))
+        .unwrap_or_default())
+}
+
+/// Gallery order of the media
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+    FileName,
+    SourceThenDate,
+    FileModified,
 }
 
-pub struct InsertionEntry<'a> {
-    pub path: &'a str,
-    pub finfo: &'a str,
+impl SortOrder {
+    pub const ALL: [SortOrder; 5] = [
+        SortOrder::NewestFirst,
+        SortOrder::OldestFirst,
+        SortOrder::FileName,
+        SortOrder::SourceThenDate,
+        SortOrder::FileModified,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortOrder::NewestFirst => "newest",
+            SortOrder::OldestFirst => "oldest",
+            SortOrder::FileName => "name",
+            SortOrder::SourceThenDate => "source",
+            SortOrder::FileModified => "modified",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        Self::ALL.into_iter().find(|order| order.name() == name)
+    }
+}
+
+pub struct LivePhotoCandidate {
+    pub path: String,
+    pub is_video: bool,
+    pub content_id: Option<String>,
+    pub duration_ms: Option<u64>,
     pub timestamp: i64,
-    pub metadata: i64,
-    pub thumbnail: &'a [u8],
+}
+
+pub struct IndexStats {
+    pub total_count: i64,
+    pub type_counts: Vec<(String, i64)>,
+    pub min_timestamp: Option<i64>,
+    pub max_timestamp: Option<i64>,
+    pub thumbnails_size: i64,
+    pub live_photo_count: i64,
+    pub motion_photo_count: i64,
+}
+
+fn upsert_entry(conn: &Connection, e: &InsertionEntry) -> rusqlite::Result<()> {
+    let m = &e.metadata;
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO media
+            (path, finfo, media_type, timestamp, utc_offset, width, height,
+            orientation, duration_ms, camera_make, camera_model, content_id,
+            motion_offset, motion_length, file_mtime, source, is_valid, thumbnail)
+        VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, 1, ?17)
+        ON CONFLICT(path) DO UPDATE SET
+            finfo = excluded.finfo,
+            file_mtime = excluded.file_mtime,
+            source = excluded.source,
+            media_type = excluded.media_type,
+            timestamp = excluded.timestamp,
+            utc_offset = excluded.utc_offset,
+            width = excluded.width,
+            height = excluded.height,
+            orientation = excluded.orientation,
+            duration_ms = excluded.duration_ms,
+            camera_make = excluded.camera_make,
+            camera_model = excluded.camera_model,
+            content_id = excluded.content_id,
+            motion_offset = excluded.motion_offset,
+            motion_length = excluded.motion_length,
+            is_valid = excluded.is_valid,
+            thumbnail = excluded.thumbnail",
+    )?;
+
+    stmt.execute(params![
+        &e.path,
+        &e.finfo,
+        e.media_type.name(),
+        m.timestamp(),
+        m.utc_offset(),
+        m.width,
+        m.height,
+        m.exif_orientation().map(i64::from),
+        m.duration_ms().map(|d| d as i64),
+        &m.camera_make,
+        &m.camera_model,
+        &e.content_id,
+        e.motion_video.map(|v| v.offset as i64),
+        e.motion_video.map(|v| v.length as i64),
+        e.file_mtime,
+        &e.source,
+        &e.thumbnail,
+    ])
+    .map(|_| ())
+}
+
+pub struct InsertionEntry {
+    pub path: String,
+    pub finfo: String,
+    pub file_mtime: i64,
+    /// Label of the source directory
+    pub source: String,
+    pub media_type: MediaType,
+    pub metadata: MediaMetadata,
+    pub content_id: Option<String>,
+    pub motion_video: Option<EmbeddedVideo>,
+    pub thumbnail: Vec<u8>,
+}
+
+/// Unchanged file that is kept in the index. Its modification time and source are still
+/// updated to fill them for the files indexed before they were stored
+pub struct ValidEntry {
+    pub path: String,
+    pub file_mtime: i64,
+    pub source: String,
+}
+
+const MEDIA_ENTRY_COLUMNS: &str = "path, media_type, timestamp, utc_offset, width, height, \
+    orientation, duration_ms, camera_make, camera_model, live_video, motion_offset, \
+    motion_length";
+
+/// Media with the same group key that go one after another in the gallery order
+pub struct MediaGroup {
+    /// Index of the first media
+    pub offset: usize,
+    pub count: usize,
+    pub key: GroupKey,
+}
+
+/// Dates are taken from the first media of the group
+pub enum GroupKey {
+    /// Period of the capture or the modification time
+    Date(NaiveDate),
+    /// Capture period of the media from the same source
+    SourceDate(String, NaiveDate),
+    /// First letter of the file name
+    Letter(String),
+}
+
+pub struct MediaEntry {
+    pub path: String,
+    pub media_type: MediaType,
+    pub datetime: DateTime<FixedOffset>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub orientation: Option<ExifOrientation>,
+    pub duration_ms: Option<u64>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub live_video: Option<String>,
+    pub motion_video: Option<EmbeddedVideo>,
+}
+
+impl MediaEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<MediaEntry> {
+        let media_type: String = row.get(1)?;
+        let media_type = MediaType::from_name(&media_type).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                1,
+                Type::Text,
+                Box::new(FromSqlError::Other(
+                    format!("Unknown media type `{media_type}`").into(),
+                )),
+            )
+        })?;
+
+        let timestamp: i64 = row.get(2)?;
+        let utc_offset: Option<i32> = row.get(3)?;
+        let offset = utc_offset
+            .and_then(FixedOffset::east_opt)
+            .unwrap_or(FixedOffset::east_opt(0).unwrap());
+        let datetime = DateTime::from_timestamp(timestamp, 0)
+            .unwrap_or_default()
+            .with_timezone(&offset);
+
+        let orientation: Option<i64> = row.get(6)?;
+        let duration_ms: Option<i64> = row.get(7)?;
+        let motion_offset: Option<i64> = row.get(11)?;
+        let motion_length: Option<i64> = row.get(12)?;
+
+        Ok(MediaEntry {
+            path: row.get(0)?,
+            media_type,
+            datetime,
+            width: row.get(4)?,
+            height: row.get(5)?,
+            orientation: orientation.and_then(|o| o.try_into().ok()),
+            duration_ms: duration_ms.map(|d| d as u64),
+            camera_make: row.get(8)?,
+            camera_model: row.get(9)?,
+            live_video: row.get(10)?,
+            motion_video: motion_offset
+                .zip(motion_length)
+                .map(|(offset, length)| EmbeddedVideo {
+                    offset: offset as u64,
+                    length: length as u64,
+                }),
+        })
+    }
+
+    /// Live Photos and motion photos have a short video shown on demand
+    pub fn has_motion(&self) -> bool {
+        self.live_video.is_some() || self.motion_video.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_old_metadata_without_reindexing() {
+        let path = std::env::temp_dir().join(format!("photoflow-{}-v1.db", std::process::id()));
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(MIGRATIONS[0]).unwrap();
+            conn.pragma_update(None, "user_version", 1).unwrap();
+            conn.execute_batch(
+                "INSERT INTO media (path, finfo, timestamp, metadata, is_valid) VALUES
+                    ('/a/IMG_1.jpg', '1:1', 100, 6, 1),
+                    ('/a/VID_1.mp4', '2:2', 200, 1500, 1),
+                    ('/a/VID_2.mp4', '3:3', 300, NULL, 1),
+                    ('/a/notes.txt', '4:4', 400, 0, 1);",
+            )
+            .unwrap();
+        }
+
+        let db = IndexDb::open(&path).unwrap();
+        let mut stmt = db
+            .conn
+            .prepare(
+                "SELECT path, finfo, media_type, orientation, duration_ms FROM media ORDER BY path",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map((), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        drop(stmt);
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+
+        let row = |path: &str, finfo: &str, mt: &str, orientation, duration_ms| {
+            (
+                path.into(),
+                finfo.into(),
+                mt.into(),
+                orientation,
+                duration_ms,
+            )
+        };
+        assert_eq!(rows[0].2, "jpeg");
+        assert_eq!(rows[0].3, Some(6));
+        assert_eq!(rows[1], row("/a/VID_1.mp4", "2:2", "mp4", None, Some(1500)));
+        assert_eq!(rows[2], row("/a/VID_2.mp4", "", "mp4", None, None));
+        assert_eq!(rows.len(), 3);
+    }
 }
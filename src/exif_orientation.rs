@@ -81,4 +81,15 @@ impl ExifOrientation {
             ExifOrientation::Rotated270 => image.rotate270(),
         }
     }
+
+    /// Orientations that swap the image width and height
+    pub fn swaps_dimensions(&self) -> bool {
+        matches!(
+            self,
+            ExifOrientation::Rotated90AndMirroredHorizontally
+                | ExifOrientation::Rotated90
+                | ExifOrientation::Rotated90AndMirroredVertically
+                | ExifOrientation::Rotated270
+        )
+    }
 }
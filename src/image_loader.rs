@@ -1,26 +1,212 @@
 use crate::exif_orientation::ExifOrientation;
-use crate::media::ImageType;
-use image::DynamicImage;
-use libheif_rs::{ColorSpace, HeifContext, LibHeif, Plane, RgbChroma};
+use crate::media::{self, ImageType};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageFormat, ImageReader, RgbImage};
+use jpeg_decoder::PixelFormat;
+use libheif_rs::{ColorSpace, HeifContext, ImageHandle, LibHeif, Plane, RgbChroma};
 use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::BufReader;
 use std::path::Path;
 
+/// Limits the memory of a full resolution image to about 150 MB of RGB pixels
+const MAX_FULL_SIZE_PIXELS: u64 = 50_000_000;
+
+/// Formats without scaled decoding are downscaled after decoding at the original resolution,
+/// so bigger images are rejected before their pixels are allocated
+const MAX_DECODED_PIXELS: u64 = 200_000_000;
+
+/// Requested resolution of a decoded image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageSize {
+    /// Fits into the bounding box keeping the aspect ratio, images are never upscaled
+    Fit(u32, u32),
+    /// The original resolution limited by the memory cap
+    Full,
+}
+
 pub fn open<P: AsRef<Path>>(path: P, image_type: ImageType) -> anyhow::Result<DecodedImage> {
     match image_type {
         ImageType::Jpeg => {
-            let image = image::open(path)?;
+            let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
             Ok(DecodedImage::WithoutTransformations(image))
         }
-        ImageType::Heic => {
-            let image = decode_heic(path)?;
+        ImageType::Heic | ImageType::Heif | ImageType::Avif => {
+            let image = decode_heif(path)?;
             Ok(DecodedImage::WithTransformations(image))
         }
+        ImageType::Png | ImageType::WebP | ImageType::Gif => {
+            // Animated GIF and WebP files are decoded to their first frame
+            let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+            Ok(DecodedImage::WithoutTransformations(flatten_alpha(image)))
+        }
+        ImageType::Raw(_) => {
+            let data = fs::read(path)?;
+            let preview = media::largest_jpeg_preview(&data)
+                .ok_or_else(|| anyhow::anyhow!("No embedded preview found"))?;
+            let image = image::load_from_memory_with_format(preview, ImageFormat::Jpeg)?;
+            Ok(DecodedImage::WithoutTransformations(image))
+        }
+    }
+}
+
+/// Decodes an image not smaller than the requested size when the format allows it.
+/// The result should be fitted into the size after applying the orientation
+pub fn open_sized<P: AsRef<Path>>(
+    path: P,
+    image_type: ImageType,
+    size: ImageSize,
+) -> anyhow::Result<DecodedImage> {
+    let path = path.as_ref();
+
+    let scaled_side = match image_type {
+        ImageType::Jpeg => jpeg_scaled_side(path, size),
+        _ => None,
+    };
+    if let Some(min_size) = scaled_side {
+        match open_jpeg_scaled(path, min_size) {
+            Ok(image) => return Ok(DecodedImage::WithoutTransformations(image)),
+            Err(e) => log::debug!("Failed to decode scaled `{}`: {e}", path.display()),
+        }
+    }
+
+    match image_type {
+        ImageType::Heic | ImageType::Heif | ImageType::Avif => {
+            let image = decode_heif_limited(path, MAX_DECODED_PIXELS)?;
+            Ok(DecodedImage::WithTransformations(image))
+        }
+        // Embedded previews are small
+        ImageType::Raw(_) => open(path, image_type),
+        _ => {
+            let (w, h) = ImageReader::open(path)?
+                .with_guessed_format()?
+                .into_dimensions()?;
+            check_decoded_pixels(w, h, MAX_DECODED_PIXELS)?;
+            open(path, image_type)
+        }
+    }
+}
+
+/// Returns the smaller side of a JPEG image decoded with the DCT scaling for the requested size.
+/// Full size images are scaled only if they exceed the memory cap
+fn jpeg_scaled_side(path: &Path, size: ImageSize) -> Option<u32> {
+    match size {
+        // The shorter side of the box is enough for any orientation of the image
+        ImageSize::Fit(w, h) => Some(w.min(h)),
+        ImageSize::Full => {
+            let reader = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+            let (w, h) = reader.into_dimensions().ok()?;
+            capped_short_side(w, h, MAX_FULL_SIZE_PIXELS)
+        }
+    }
+}
+
+/// Returns the smaller side of the image downscaled to the pixel limit if it exceeds it
+fn capped_short_side(w: u32, h: u32, max_pixels: u64) -> Option<u32> {
+    let pixels = u64::from(w) * u64::from(h);
+    if pixels <= max_pixels {
+        return None;
+    }
+
+    let scale = (max_pixels as f64 / pixels as f64).sqrt();
+    Some(((w.min(h) as f64 * scale) as u32).max(1))
+}
+
+fn check_decoded_pixels(w: u32, h: u32, max_pixels: u64) -> anyhow::Result<()> {
+    if u64::from(w) * u64::from(h) > max_pixels {
+        return Err(anyhow::anyhow!(
+            "Image resolution {w}x{h} exceeds the limit of {max_pixels} pixels"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Downscales the image to the requested size
+fn fit(image: DynamicImage, size: ImageSize) -> DynamicImage {
+    let (w, h) = image.dimensions();
+
+    let (max_w, max_h) = match size {
+        ImageSize::Fit(max_w, max_h) => (max_w.max(1), max_h.max(1)),
+        ImageSize::Full => {
+            let pixels = u64::from(w) * u64::from(h);
+            if pixels <= MAX_FULL_SIZE_PIXELS {
+                return image;
+            }
+
+            let scale = (MAX_FULL_SIZE_PIXELS as f64 / pixels as f64).sqrt();
+            ((w as f64 * scale) as u32, (h as f64 * scale) as u32)
+        }
+    };
+
+    if w <= max_w && h <= max_h {
+        return image;
+    }
+
+    // Lanczos is too slow for big photos
+    image.resize(max_w, max_h, FilterType::Triangle)
+}
+
+/// Decodes a JPEG image with the DCT scaling, so its smaller side is not less than `min_size`.
+/// It is much faster than the full decoding of a big image. CMYK and 16-bit images are not supported
+pub fn open_jpeg_scaled<P: AsRef<Path>>(path: P, min_size: u32) -> anyhow::Result<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.read_info()?;
+
+    let info = decoder
+        .info()
+        .ok_or_else(|| anyhow::anyhow!("Missing JPEG info"))?;
+    if !matches!(info.pixel_format, PixelFormat::RGB24 | PixelFormat::L8) {
+        return Err(anyhow::anyhow!(
+            "Unsupported JPEG pixel format {:?}",
+            info.pixel_format
+        ));
     }
+
+    let (w, h) = (u32::from(info.width), u32::from(info.height));
+    let short_side = w.min(h).max(1);
+    let requested = |side: u32| (side * min_size).div_ceil(short_side).min(u16::MAX.into()) as u16;
+    let (w, h) = decoder.scale(requested(w), requested(h))?;
+
+    let pixels = decoder.decode()?;
+    let (w, h) = (u32::from(w), u32::from(h));
+    let image = match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8),
+        _ => RgbImage::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8),
+    };
+
+    image.ok_or_else(|| anyhow::anyhow!("Invalid JPEG buffer"))
 }
 
-fn decode_heic<P: AsRef<Path>>(path: P) -> anyhow::Result<DynamicImage> {
+/// Blends transparent pixels with a neutral gray, because thumbnails and viewer buffers are RGB
+fn flatten_alpha(image: DynamicImage) -> DynamicImage {
+    const BACKGROUND: u32 = 0x80;
+
+    if !image.color().has_alpha() {
+        return image;
+    }
+
+    let rgba = image.into_rgba8();
+    let rgb = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let a = u32::from(a);
+        let blend = |c: u8| ((u32::from(c) * a + BACKGROUND * (255 - a) + 127) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    });
+
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Decodes HEIF container images with HEVC (HEIC) or AV1 (AVIF) compression.
+/// The container transformations like rotation are applied by libheif
+fn decode_heif<P: AsRef<Path>>(path: P) -> anyhow::Result<DynamicImage> {
+    decode_heif_limited(path.as_ref(), u64::MAX)
+}
+
+/// Decodes the primary HEIF image if its resolution is within the limit.
+/// HEIF images can't be decoded at a lower resolution, so the limit is checked before decoding
+fn decode_heif_limited(path: &Path, max_pixels: u64) -> anyhow::Result<DynamicImage> {
     let path = path
-        .as_ref()
         .as_os_str()
         .to_str()
         .ok_or_else(|| anyhow::anyhow!("Failed to convert path to str"))?;
@@ -28,8 +214,14 @@ fn decode_heic<P: AsRef<Path>>(path: P) -> anyhow::Result<DynamicImage> {
     let read_ctx = HeifContext::read_from_file(path)?;
     let handle = read_ctx.primary_image_handle()?;
 
+    check_decoded_pixels(handle.width(), handle.height(), max_pixels)?;
+    decode_heif_handle(&handle)
+}
+
+/// Decodes the primary image or a thumbnail of a HEIF container
+pub fn decode_heif_handle(handle: &ImageHandle) -> anyhow::Result<DynamicImage> {
     let lib_heif = LibHeif::new();
-    let heif_image = lib_heif.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
+    let heif_image = lib_heif.decode(handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
 
     let plane = heif_image
         .planes()
@@ -74,6 +266,20 @@ impl DecodedImage {
         }
     }
 
+    /// Downscales the image before applying the orientation, so fewer pixels are rotated
+    pub fn fit_oriented(self, size: ImageSize, orientation: ExifOrientation) -> DynamicImage {
+        match self {
+            DecodedImage::WithTransformations(image) => fit(image, size),
+            DecodedImage::WithoutTransformations(image) => {
+                let size = match size {
+                    ImageSize::Fit(w, h) if orientation.swaps_dimensions() => ImageSize::Fit(h, w),
+                    size => size,
+                };
+                orientation.apply(fit(image, size))
+            }
+        }
+    }
+
     pub fn map<F: FnOnce(DynamicImage) -> DynamicImage>(self, f: F) -> Self {
         match self {
             DecodedImage::WithTransformations(i) => DecodedImage::WithTransformations(f(i)),
@@ -81,3 +287,60 @@ impl DecodedImage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::new(w, h))
+    }
+
+    #[test]
+    fn fits_without_upscaling() {
+        assert_eq!(
+            fit(image(400, 200), ImageSize::Fit(100, 100)).dimensions(),
+            (100, 50)
+        );
+        assert_eq!(
+            fit(image(40, 20), ImageSize::Fit(100, 100)).dimensions(),
+            (40, 20)
+        );
+        assert_eq!(fit(image(40, 20), ImageSize::Full).dimensions(), (40, 20));
+    }
+
+    #[test]
+    fn fits_before_orientation() {
+        let fitted = |decoded: DecodedImage, orientation| {
+            decoded
+                .fit_oriented(ImageSize::Fit(200, 100), orientation)
+                .dimensions()
+        };
+
+        let decoded = DecodedImage::WithoutTransformations(image(400, 200));
+        assert_eq!(fitted(decoded, ExifOrientation::Unchanged), (200, 100));
+        let decoded = DecodedImage::WithoutTransformations(image(400, 200));
+        assert_eq!(fitted(decoded, ExifOrientation::Rotated90), (50, 100));
+        let decoded = DecodedImage::WithoutTransformations(image(200, 400));
+        assert_eq!(fitted(decoded, ExifOrientation::Rotated270), (200, 100));
+        let decoded = DecodedImage::WithTransformations(image(200, 400));
+        assert_eq!(fitted(decoded, ExifOrientation::Rotated90), (50, 100));
+    }
+
+    #[test]
+    fn caps_full_size() {
+        assert_eq!(capped_short_side(400, 200, 80_000), None);
+        assert_eq!(capped_short_side(400, 200, 20_000), Some(100));
+        assert_eq!(capped_short_side(200, 400, 20_000), Some(100));
+        assert_eq!(capped_short_side(u32::MAX, 1, 1), Some(1));
+
+        assert!(check_decoded_pixels(400, 200, 80_000).is_ok());
+        assert!(check_decoded_pixels(400, 201, 80_000).is_err());
+
+        // Bigger images are downscaled instead of being rejected
+        assert_eq!(
+            fit(image(400, 200), ImageSize::Full).dimensions(),
+            (400, 200)
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::db::{IndexDb, LivePhotoCandidate};
+use std::collections::HashMap;
+
+/// Motion parts are about three seconds long. Files without the content identifiers are paired
+/// by the name only if the video is short enough
+const MAX_MOTION_DURATION_MS: u64 = 5000;
+
+#[derive(Default)]
+struct Group<'a> {
+    stills: Vec<&'a LivePhotoCandidate>,
+    videos: Vec<&'a LivePhotoCandidate>,
+}
+
+/// Links the stills of Live Photos with their motion parts located in the same directory
+/// and having the same name (like `IMG_1234.HEIC` and `IMG_1234.MOV`)
+pub fn pair_live_photos(db: &mut IndexDb) -> anyhow::Result<()> {
+    let candidates = db.get_live_photo_candidates()?;
+    db.set_live_photo_pairs(&find_pairs(&candidates))?;
+    Ok(())
+}
+
+/// Returns the `(still, video)` path pairs. Each file is paired at most once,
+/// the closest capture time is preferred if a video matches several stills (like `IMG_1.HEIC`
+/// and its converted copy `IMG_1.JPG`)
+fn find_pairs(candidates: &[LivePhotoCandidate]) -> Vec<(String, String)> {
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    for candidate in candidates {
+        let group = groups.entry(path_stem_key(&candidate.path)).or_default();
+        if candidate.is_video {
+            group.videos.push(candidate);
+        } else {
+            group.stills.push(candidate);
+        }
+    }
+
+    groups.values().flat_map(Group::pairs).collect()
+}
+
+impl Group<'_> {
+    fn pairs(&self) -> Vec<(String, String)> {
+        let mut matches = Vec::new();
+        for (still_idx, still) in self.stills.iter().enumerate() {
+            for (video_idx, video) in self.videos.iter().enumerate() {
+                if is_motion_of(video, still) {
+                    let time_diff = still.timestamp.abs_diff(video.timestamp);
+                    matches.push((time_diff, still_idx, video_idx));
+                }
+            }
+        }
+        matches.sort();
+
+        let mut paired_stills = vec![false; self.stills.len()];
+        let mut paired_videos = vec![false; self.videos.len()];
+        let mut pairs = Vec::new();
+        for (_, still_idx, video_idx) in matches {
+            if paired_stills[still_idx] || paired_videos[video_idx] {
+                continue;
+            }
+
+            paired_stills[still_idx] = true;
+            paired_videos[video_idx] = true;
+            let (still, video) = (self.stills[still_idx], self.videos[video_idx]);
+            pairs.push((still.path.clone(), video.path.clone()));
+        }
+
+        pairs
+    }
+}
+
+/// Path without the extension in lower case
+fn path_stem_key(path: &str) -> String {
+    let stem = match path.rsplit_once('.') {
+        Some((stem, ext)) if !ext.contains('/') => stem,
+        _ => path,
+    };
+    stem.to_lowercase()
+}
+
+/// Files are matched by the content identifier. The name-only match is used
+/// when neither of the files has the identifier
+fn is_motion_of(video: &LivePhotoCandidate, still: &LivePhotoCandidate) -> bool {
+    match (&video.content_id, &still.content_id) {
+        (Some(video_id), Some(still_id)) => video_id == still_id,
+        (None, None) => video
+            .duration_ms
+            .is_some_and(|duration| duration <= MAX_MOTION_DURATION_MS),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn still(path: &str, content_id: Option<&str>) -> LivePhotoCandidate {
+        LivePhotoCandidate {
+            path: path.into(),
+            is_video: false,
+            content_id: content_id.map(String::from),
+            duration_ms: None,
+            timestamp: 0,
+        }
+    }
+
+    fn video(path: &str, content_id: Option<&str>, duration_ms: u64) -> LivePhotoCandidate {
+        LivePhotoCandidate {
+            path: path.into(),
+            is_video: true,
+            content_id: content_id.map(String::from),
+            duration_ms: Some(duration_ms),
+            timestamp: 0,
+        }
+    }
+
+    fn at(timestamp: i64, candidate: LivePhotoCandidate) -> LivePhotoCandidate {
+        LivePhotoCandidate {
+            timestamp,
+            ..candidate
+        }
+    }
+
+    fn sorted_pairs(candidates: &[LivePhotoCandidate]) -> Vec<(String, String)> {
+        let mut pairs = find_pairs(candidates);
+        pairs.sort();
+        pairs
+    }
+
+    fn pair(still: &str, video: &str) -> (String, String) {
+        (still.into(), video.into())
+    }
+
+    #[test]
+    fn pairs_by_content_id() {
+        let candidates = [
+            still("/a/IMG_1.HEIC", Some("id-1")),
+            video("/a/IMG_1.mov", Some("id-2"), 3000),
+            video("/a/img_1.MOV", Some("id-1"), 3000),
+            still("/a/IMG_2.JPG", Some("id-3")),
+            video("/a/IMG_2.MOV", Some("id-4"), 3000),
+            still("/b/IMG_1.HEIC", Some("id-1")),
+        ];
+
+        assert_eq!(
+            sorted_pairs(&candidates),
+            [pair("/a/IMG_1.HEIC", "/a/img_1.MOV")]
+        );
+    }
+
+    #[test]
+    fn pairs_by_name_without_content_ids() {
+        let candidates = [
+            still("/a/IMG_1.JPG", None),
+            video("/a/IMG_1.MOV", None, 3000),
+            still("/a/IMG_2.JPG", None),
+            video("/a/IMG_2.MOV", None, 60_000),
+            still("/a/IMG_3.JPG", None),
+            video("/a/IMG_3.MOV", Some("id-3"), 3000),
+            still("/a/IMG_4.JPG", Some("id-4")),
+            video("/a/IMG_4.MOV", None, 3000),
+        ];
+
+        assert_eq!(
+            sorted_pairs(&candidates),
+            [pair("/a/IMG_1.JPG", "/a/IMG_1.MOV")]
+        );
+    }
+
+    #[test]
+    fn pairs_each_video_once() {
+        let candidates = [
+            at(1000, still("/a/IMG_1.JPG", None)),
+            at(100, still("/a/IMG_1.HEIC", None)),
+            at(101, video("/a/IMG_1.MOV", None, 3000)),
+            at(0, still("/a/IMG_2.JPG", Some("id-2"))),
+            at(500, still("/a/IMG_2.HEIC", Some("id-2"))),
+            at(501, video("/a/IMG_2.MOV", Some("id-2"), 3000)),
+            at(0, still("/a/IMG_3.JPG", None)),
+            at(0, still("/a/IMG_3.HEIC", None)),
+            at(0, video("/a/IMG_3.MOV", None, 3000)),
+        ];
+
+        let pairs = sorted_pairs(&candidates);
+        assert_eq!(
+            pairs[..2],
+            [
+                pair("/a/IMG_1.HEIC", "/a/IMG_1.MOV"),
+                pair("/a/IMG_2.HEIC", "/a/IMG_2.MOV"),
+            ]
+        );
+        // Any of the stills taken at the same time
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs[2].1, "/a/IMG_3.MOV");
+    }
+
+    #[test]
+    fn groups_by_case_folded_stem() {
+        assert_eq!(path_stem_key("/a/IMG_1.HEIC"), "/a/img_1");
+        assert_eq!(path_stem_key("/a/IMG_1.tar.MOV"), "/a/img_1.tar");
+        assert_eq!(path_stem_key("/a.b/IMG_1"), "/a.b/img_1");
+    }
+}
@@ -1,174 +1,263 @@
+mod live_photo;
 mod preview_loader;
+mod progress;
 mod thumbnail;
+mod watcher;
+mod writer;
 
-use crate::db::{IndexDb, InsertionEntry};
-use crate::media::{MediaMetadata, MediaType};
+use self::progress::AppProgress;
+use self::watcher::SourceWatcher;
+use self::writer::{IndexWriter, WriteRequest};
+use crate::config::Source;
+use crate::db::{IndexDb, InsertionEntry, ValidEntry};
+use crate::media::{
+    ImageType, MediaMetadata, MediaType, MetadataParser, find_embedded_video, live_photo_content_id,
+};
 use crate::ui::PhotoFlowApp;
 use anyhow::anyhow;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 use image::DynamicImage;
 use image::codecs::jpeg::JpegEncoder;
-use nom_exif::MediaParser;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use slint::Weak;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use walkdir::{DirEntry, WalkDir};
+use std::sync::mpsc::Sender;
+use walkdir::WalkDir;
 
+pub use self::live_photo::pair_live_photos;
+pub use self::preview_loader::PreviewCounts;
+pub use self::progress::IndexProgress;
+
+const THUMBNAIL_SIZE: u32 = 470;
+
+/// Updates the index and then keeps it updated while the sources are changing.
+/// `on_update` is called each time the index is updated after the initial indexing
 pub fn update_index_bg(
-    sources: Vec<String>,
-    db: Arc<Mutex<IndexDb>>,
+    sources: Vec<Source>,
+    db: IndexDb,
     weak_app: Weak<PhotoFlowApp>,
     on_start: impl FnOnce(PhotoFlowApp, i32) + Send + 'static,
     on_finish: impl FnOnce(PhotoFlowApp) + Send + 'static,
+    on_update: impl Fn(PhotoFlowApp) + Send + Clone + 'static,
 ) {
     rayon::spawn(move || {
-        if let Err(e) = update_index(sources, db, weak_app.clone(), on_start, on_finish) {
-            log::error!("Update Index error: {e}");
-            let _ = weak_app.upgrade_in_event_loop(move |app| {
-                app.set_indexing_error(format!("{e}").into());
-            });
+        // Start watching before the scan, so the changes made during indexing are not missed
+        let watcher = SourceWatcher::new(&sources)
+            .inspect_err(|e| log::error!("Failed to watch sources: {e}"))
+            .ok();
+
+        let progress = AppProgress::new(weak_app.clone(), on_start);
+        let result = update_index(&sources, db, &progress).and_then(|db| {
+            weak_app.upgrade_in_event_loop(on_finish)?;
+            Ok(db)
+        });
+
+        match result {
+            Ok(db) => {
+                if let Some(watcher) = watcher {
+                    watcher.spawn(db, weak_app, on_update);
+                }
+            }
+            Err(e) => {
+                log::error!("Update Index error: {e}");
+                let _ = weak_app.upgrade_in_event_loop(move |app| {
+                    app.set_indexing_error(format!("{e}").into());
+                    app.set_indexing_in_progress(false);
+                });
+            }
         }
     });
 }
 
-fn update_index(
-    sources: Vec<String>,
-    db: Arc<Mutex<IndexDb>>,
-    weak_app: Weak<PhotoFlowApp>,
-    on_start: impl FnOnce(PhotoFlowApp, i32) + Send + 'static,
-    on_finish: impl FnOnce(PhotoFlowApp) + Send + 'static,
-) -> anyhow::Result<()> {
-    {
-        let db = db.lock().map_err(|_| anyhow!("Failed to lock IndexDB"))?;
-        db.create_index_if_not_exists()?;
-        db.invalidate_index()?;
-    }
+/// Indexes new and changed files of the sources and removes the entries of deleted files
+pub fn update_index(
+    sources: &[Source],
+    db: IndexDb,
+    progress: &impl IndexProgress,
+) -> anyhow::Result<IndexDb> {
+    db.invalidate_index()?;
+    let indexed = db.get_finfo_map()?;
 
-    let mut paths = HashSet::new();
+    let mut paths = HashMap::new();
     for source in sources {
-        collect_paths(source, &mut paths);
+        collect_paths(source, &source.path, &mut paths);
     }
 
-    let len = paths.len() as i32;
-    weak_app.upgrade_in_event_loop(move |app| {
-        on_start(app, len);
-    })?;
-
-    index_parallel(&db, &paths, weak_app.clone());
+    progress.start(paths.len());
 
-    {
-        let db = db.lock().map_err(|_| anyhow!("Failed to lock IndexDB"))?;
-        db.cleanup_index()?;
-        db.rebuild_order_table()?;
-    }
+    let writer = IndexWriter::spawn(db)?;
+    let previews = index_parallel(writer.sender(), &indexed, &paths, progress);
+    let mut db = writer.finish()?;
+    progress.finish(&previews);
 
-    weak_app.upgrade_in_event_loop(on_finish)?;
+    db.cleanup_index()?;
+    pair_live_photos(&mut db)?;
+    db.rebuild_order_table()?;
 
-    Ok(())
+    Ok(db)
 }
 
-fn collect_paths<P: AsRef<Path>>(source: P, target: &mut HashSet<PathBuf>) {
-    let it = WalkDir::new(source)
+/// Collects media files of the source located in the `dir` (the source directory or its subdirectory)
+fn collect_paths<'a>(source: &'a Source, dir: &Path, target: &mut HashMap<PathBuf, &'a Source>) {
+    let it = WalkDir::new(dir)
+        .follow_links(source.follow_symlinks)
         .into_iter()
+        .filter_entry(|e| !e.file_type().is_dir() || source.accepts_dir(e.path()))
         .filter_map(|r| r.ok())
-        .filter(is_not_hidden)
-        .filter(|e| MediaType::from_path(e.path()).is_some())
-        .map(|e| e.path().to_path_buf());
+        .filter(|e| !e.file_type().is_dir() && source.accepts(e.path()))
+        .filter(|e| is_media_file(e.path(), source))
+        .map(|e| (e.path().to_path_buf(), source));
     target.extend(it);
 }
 
-fn is_not_hidden(entry: &DirEntry) -> bool {
-    entry
-        .path()
-        .file_name()
-        .and_then(|name| name.to_str())
-        .map(|name_str| !name_str.starts_with("."))
-        .unwrap_or(false)
+/// Checks if the indexing of the sources would pick the file
+pub fn is_source_media(sources: &[Source], path: &Path) -> bool {
+    sources
+        .iter()
+        .any(|source| source.accepts(path) && is_media_file(path, source))
 }
 
-fn index_parallel(db: &Mutex<IndexDb>, paths: &HashSet<PathBuf>, weak_app: Weak<PhotoFlowApp>) {
-    let media_parser = Mutex::new(MediaParser::new());
-    let weak_app = Mutex::new(weak_app);
-
-    paths.par_iter().for_each(move |path| {
-        if let Err(e) = index_file(path, db, &media_parser) {
-            log::error!(
-                "Failed to index file `{}`: {}",
-                path.to_str().unwrap_or_default(),
-                e
-            );
-        }
+/// Checks the media type of an accepted file by its extension. Only extensionless files are sniffed
+/// here, the exact type of other files is detected on indexing.
+/// RAW files can be hidden by their JPEG siblings
+fn is_media_file(path: &Path, source: &Source) -> bool {
+    let media_type = match path.extension() {
+        Some(ext) => MediaType::from_ext(ext),
+        None => MediaType::detect(path),
+    };
 
-        {
-            let weak_app = weak_app.lock().unwrap();
-            let _ = weak_app.upgrade_in_event_loop(move |app| {
-                app.set_indexing_processed(app.get_indexing_processed() + 1);
-            });
+    match media_type {
+        Some(media_type) if media_type.is_raw() && source.hide_raw_with_jpeg => {
+            !has_jpeg_sibling(path)
         }
-    });
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Looks for a JPEG file with the same stem in the directory listing,
+/// so extensions of any case (like `.Jpg` or `.JPE`) are matched
+fn has_jpeg_sibling(path: &Path) -> bool {
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return false;
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+
+    entries.filter_map(|e| e.ok()).any(|e| {
+        let sibling = e.path();
+        sibling.file_stem() == Some(stem)
+            && sibling
+                .extension()
+                .and_then(ImageType::from_ext)
+                .is_some_and(|image_type| matches!(image_type, ImageType::Jpeg))
+            && sibling.is_file()
+    })
+}
+
+fn index_parallel(
+    writer: &Sender<WriteRequest>,
+    indexed: &HashMap<String, String>,
+    paths: &HashMap<PathBuf, &Source>,
+    progress: &impl IndexProgress,
+) -> PreviewCounts {
+    let previews = PreviewCounts::default();
+
+    paths
+        .par_iter()
+        .for_each_init(MetadataParser::new, |parser, (path, source)| {
+            let result = worker_parser(parser)
+                .and_then(|parser| index_file(path, source, indexed, parser, &previews))
+                .and_then(|request| {
+                    writer
+                        .send(request)
+                        .map_err(|_| anyhow!("Index writer is stopped"))
+                });
+
+            if let Err(e) = result {
+                log::error!(
+                    "Failed to index file `{}`: {}",
+                    path.to_str().unwrap_or_default(),
+                    e
+                );
+            }
+
+            progress.file_processed();
+        });
+
+    previews
+}
+
+/// Each worker creates its own parser, so a slow file doesn't block the other workers
+fn worker_parser(
+    parser: &mut anyhow::Result<MetadataParser>,
+) -> anyhow::Result<&mut MetadataParser> {
+    parser
+        .as_mut()
+        .map_err(|e| anyhow!("Failed to create metadata parser: {e}"))
 }
 
 fn index_file<P: AsRef<Path>>(
     path: P,
-    db: &Mutex<IndexDb>,
-    mp: &Mutex<MediaParser>,
-) -> anyhow::Result<()> {
+    source: &Source,
+    indexed: &HashMap<String, String>,
+    parser: &mut MetadataParser,
+    previews: &PreviewCounts,
+) -> anyhow::Result<WriteRequest> {
     let path_str = path
         .as_ref()
         .to_str()
         .ok_or_else(|| anyhow!("Non-unicode path"))?;
 
     let file_meta = fs::metadata(&path)?;
-    let finfo = get_finfo_str(&file_meta)?;
+    let file_mtime = DateTime::<Utc>::from(file_meta.modified()?).timestamp();
+    let finfo = get_finfo_str(file_meta.len(), file_mtime, source.clock_offset);
 
-    {
-        let db = db.lock().map_err(|_| anyhow!("Failed to lock IndexDB"))?;
-        if db.set_valid_if_unchanged(path_str, &finfo)? {
-            return Ok(());
-        }
+    if indexed.get(path_str) == Some(&finfo) {
+        return Ok(WriteRequest::SetValid(ValidEntry {
+            path: path_str.to_string(),
+            file_mtime,
+            source: source.label.clone(),
+        }));
     }
 
-    let media_type = MediaType::from_path(&path).ok_or_else(|| anyhow!("Invalid media type"))?;
-    let media_meta = {
-        let mut mp = mp.lock().unwrap();
-        MediaMetadata::parse(&path, &media_type, &mut mp)?
-    };
+    let media_type = MediaType::detect(&path).ok_or_else(|| anyhow!("Invalid media type"))?;
+    let media_meta = MediaMetadata::parse(&path, &media_type, parser, source.clock_offset)?;
+
+    let (preview, preview_source) = preview_loader::open(&path, &media_type, THUMBNAIL_SIZE)?;
+    previews.add(preview_source);
 
-    let preview = preview_loader::open(&path, &media_type)?;
     let thumbnail = preview
-        .map(|img| thumbnail::squared(&img, 470))
+        .map(|img| thumbnail::squared(&img, THUMBNAIL_SIZE))
         .oriented(media_meta.exif_orientation().unwrap_or_default());
 
-    let entry = InsertionEntry {
-        path: path_str,
-        finfo: &finfo,
-        timestamp: media_meta.timestamp(),
-        metadata: db_meta(&media_meta),
-        thumbnail: &encode_jpeg(&thumbnail)?,
-    };
-
-    {
-        let db = db.lock().map_err(|_| anyhow!("Failed to lock IndexDB"))?;
-        db.upsert_entry(&entry)?;
-    }
-
-    Ok(())
+    Ok(WriteRequest::Upsert(InsertionEntry {
+        path: path_str.to_string(),
+        finfo,
+        file_mtime,
+        source: source.label.clone(),
+        media_type,
+        metadata: media_meta,
+        content_id: live_photo_content_id(path.as_ref(), &media_type),
+        motion_video: match media_type {
+            MediaType::Image(ImageType::Jpeg) => find_embedded_video(path.as_ref()),
+            _ => None,
+        },
+        thumbnail: encode_jpeg(&thumbnail)?,
+    }))
 }
 
-fn db_meta(metadata: &MediaMetadata) -> i64 {
-    match metadata {
-        MediaMetadata::Image { orientation, .. } => (*orientation).into(),
-        MediaMetadata::Video { duration_ms, .. } => *duration_ms as i64,
+/// The file info also includes the clock offset of its source to reindex the file when it changes
+fn get_finfo_str(len: u64, mtime: i64, clock_offset: TimeDelta) -> String {
+    let mut formatted = format!("{len:x}:{mtime:x}");
+    if !clock_offset.is_zero() {
+        formatted.push_str(&format!(":{:+}", clock_offset.num_seconds()));
     }
-}
 
-fn get_finfo_str(m: &fs::Metadata) -> anyhow::Result<String> {
-    let modified: DateTime<Utc> = m.modified()?.into();
-    let formatted = format!("{:x}:{:x}", m.len(), modified.timestamp());
-    Ok(formatted)
+    formatted
 }
 
 fn encode_jpeg(image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
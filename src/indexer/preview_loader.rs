@@ -1,21 +1,158 @@
 use crate::image_loader;
 use crate::image_loader::DecodedImage;
-use crate::media::MediaType;
+use crate::media::{self, ImageType, MediaType};
 use anyhow::anyhow;
 use gstreamer::prelude::{Cast, ElementExt, GstBinExt, IsA, ObjectExt};
 use gstreamer::FlowSuccess;
 use gstreamer_app::{AppSink, AppSinkCallbacks};
 use gstreamer_video::{VideoCapsBuilder, VideoFormat, VideoFrameExt, VideoFrameRef, VideoInfo};
 use image::flat::NormalForm;
-use image::{DynamicImage, FlatSamples, RgbImage};
+use image::{DynamicImage, FlatSamples, ImageFormat, RgbImage};
+use libheif_rs::HeifContext;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
-pub fn open<P: AsRef<Path>>(path: P, mt: &MediaType) -> anyhow::Result<DecodedImage> {
+/// Some cameras add black bars to the EXIF thumbnail to fit it into 4:3
+const MAX_ASPECT_RATIO_DIFF: f32 = 0.02;
+
+/// The way a preview is loaded
+#[derive(Clone, Copy)]
+pub enum PreviewSource {
+    ExifThumbnail,
+    HeifThumbnail,
+    ScaledJpeg,
+    FullImage,
+    VideoFrame,
+}
+
+/// Number of previews loaded in each way
+#[derive(Default)]
+pub struct PreviewCounts {
+    exif_thumbnail: AtomicUsize,
+    heif_thumbnail: AtomicUsize,
+    scaled_jpeg: AtomicUsize,
+    full_image: AtomicUsize,
+    video_frame: AtomicUsize,
+}
+
+impl PreviewCounts {
+    pub fn add(&self, source: PreviewSource) {
+        let counter = match source {
+            PreviewSource::ExifThumbnail => &self.exif_thumbnail,
+            PreviewSource::HeifThumbnail => &self.heif_thumbnail,
+            PreviewSource::ScaledJpeg => &self.scaled_jpeg,
+            PreviewSource::FullImage => &self.full_image,
+            PreviewSource::VideoFrame => &self.video_frame,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for PreviewCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        write!(
+            f,
+            "EXIF thumbnails: {}, HEIF thumbnails: {}, scaled JPEG: {}, full images: {}, video frames: {}",
+            count(&self.exif_thumbnail),
+            count(&self.heif_thumbnail),
+            count(&self.scaled_jpeg),
+            count(&self.full_image),
+            count(&self.video_frame),
+        )
+    }
+}
+
+/// Loads an image that is not smaller than `size` on its shorter side when possible.
+/// Embedded thumbnails and the JPEG DCT scaling are tried before the full image decoding
+pub fn open<P: AsRef<Path>>(
+    path: P,
+    mt: &MediaType,
+    size: u32,
+) -> anyhow::Result<(DecodedImage, PreviewSource)> {
+    let path = path.as_ref();
+
     match mt {
-        MediaType::Image(img_type) => image_loader::open(&path, *img_type),
-        MediaType::Video(_) => get_video_preview(&path),
+        MediaType::Image(ImageType::Jpeg) => {
+            if let Some(image) = exif_thumbnail(path, size) {
+                let image = DecodedImage::WithoutTransformations(image);
+                return Ok((image, PreviewSource::ExifThumbnail));
+            }
+
+            match image_loader::open_jpeg_scaled(path, size) {
+                Ok(image) => {
+                    let image = DecodedImage::WithoutTransformations(image);
+                    return Ok((image, PreviewSource::ScaledJpeg));
+                }
+                Err(e) => log::debug!("Failed to decode scaled `{}`: {e}", path.display()),
+            }
+
+            let image = image_loader::open(path, ImageType::Jpeg)?;
+            Ok((image, PreviewSource::FullImage))
+        }
+        MediaType::Image(img_type @ (ImageType::Heic | ImageType::Heif | ImageType::Avif)) => {
+            if let Some(image) = heif_thumbnail(path, size) {
+                let image = DecodedImage::WithTransformations(image);
+                return Ok((image, PreviewSource::HeifThumbnail));
+            }
+
+            let image = image_loader::open(path, *img_type)?;
+            Ok((image, PreviewSource::FullImage))
+        }
+        MediaType::Image(img_type) => {
+            let image = image_loader::open(path, *img_type)?;
+            Ok((image, PreviewSource::FullImage))
+        }
+        MediaType::Video(_) => Ok((get_video_preview(path)?, PreviewSource::VideoFrame)),
+    }
+}
+
+/// The EXIF thumbnail referenced by IFD1 is usually too small, but some cameras write a bigger one
+fn exif_thumbnail(path: &Path, size: u32) -> Option<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(File::open(path).ok()?));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let thumbnail = media::exif_thumbnail(decoder.exif_data()?)?;
+
+    let mut thumbnail_decoder = jpeg_decoder::Decoder::new(thumbnail);
+    thumbnail_decoder.read_info().ok()?;
+    let thumbnail_info = thumbnail_decoder.info()?;
+
+    let thumbnail_size = (thumbnail_info.width.into(), thumbnail_info.height.into());
+    let image_size = (info.width.into(), info.height.into());
+    if !is_suitable_thumbnail(thumbnail_size, image_size, size) {
+        return None;
     }
+
+    image::load_from_memory_with_format(thumbnail, ImageFormat::Jpeg).ok()
+}
+
+/// Picks the smallest suitable thumbnail of the primary image
+fn heif_thumbnail(path: &Path, size: u32) -> Option<DynamicImage> {
+    let ctx = HeifContext::read_from_file(path.to_str()?).ok()?;
+    let handle = ctx.primary_image_handle().ok()?;
+    let image_size = (handle.width(), handle.height());
+
+    let mut ids = vec![0; handle.number_of_thumbnails()];
+    let count = handle.thumbnail_ids(&mut ids);
+
+    let thumbnail = ids[..count]
+        .iter()
+        .filter_map(|id| handle.thumbnail(*id).ok())
+        .filter(|t| is_suitable_thumbnail((t.width(), t.height()), image_size, size))
+        .min_by_key(|t| t.width() * t.height())?;
+
+    image_loader::decode_heif_handle(&thumbnail).ok()
+}
+
+fn is_suitable_thumbnail(thumbnail: (u32, u32), image: (u32, u32), size: u32) -> bool {
+    let ratio = |(w, h): (u32, u32)| w as f32 / h.max(1) as f32;
+    thumbnail.0.min(thumbnail.1) >= size
+        && (ratio(thumbnail) - ratio(image)).abs() < MAX_ASPECT_RATIO_DIFF
 }
 
 fn get_video_preview<P: AsRef<Path>>(path: P) -> anyhow::Result<DecodedImage> {
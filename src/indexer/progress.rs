@@ -0,0 +1,56 @@
+use super::PreviewCounts;
+use crate::ui::PhotoFlowApp;
+use slint::Weak;
+use std::sync::Mutex;
+
+/// Receives the indexing progress. The methods are called from the indexing worker threads
+pub trait IndexProgress: Sync {
+    fn start(&self, total: usize);
+    fn file_processed(&self);
+    /// Called after all files are processed
+    fn finish(&self, previews: &PreviewCounts);
+}
+
+/// Reports the progress to the UI
+pub struct AppProgress<F> {
+    weak_app: Mutex<Weak<PhotoFlowApp>>,
+    on_start: Mutex<Option<F>>,
+}
+
+impl<F> AppProgress<F>
+where
+    F: FnOnce(PhotoFlowApp, i32) + Send + 'static,
+{
+    pub fn new(weak_app: Weak<PhotoFlowApp>, on_start: F) -> Self {
+        Self {
+            weak_app: Mutex::new(weak_app),
+            on_start: Mutex::new(Some(on_start)),
+        }
+    }
+}
+
+impl<F> IndexProgress for AppProgress<F>
+where
+    F: FnOnce(PhotoFlowApp, i32) + Send + 'static,
+{
+    fn start(&self, total: usize) {
+        let Some(on_start) = self.on_start.lock().unwrap().take() else {
+            return;
+        };
+
+        let total = total as i32;
+        let weak_app = self.weak_app.lock().unwrap();
+        let _ = weak_app.upgrade_in_event_loop(move |app| on_start(app, total));
+    }
+
+    fn file_processed(&self) {
+        let weak_app = self.weak_app.lock().unwrap();
+        let _ = weak_app.upgrade_in_event_loop(move |app| {
+            app.set_indexing_processed(app.get_indexing_processed() + 1);
+        });
+    }
+
+    fn finish(&self, previews: &PreviewCounts) {
+        log::info!("Loaded previews: {previews}");
+    }
+}
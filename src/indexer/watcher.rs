@@ -0,0 +1,258 @@
+use super::PreviewCounts;
+use super::writer::WriteRequest;
+use crate::config::Source;
+use crate::db::IndexDb;
+use crate::media::MetadataParser;
+use crate::ui::PhotoFlowApp;
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use slint::Weak;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A file is indexed only after it is not touched for this time (e.g. when it is being copied)
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches the configured sources for created, modified, moved and deleted files
+pub struct SourceWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    sources: Vec<Source>,
+}
+
+impl SourceWatcher {
+    pub fn new(sources: &[Source]) -> anyhow::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        for source in sources {
+            if let Err(e) = watcher.watch(&source.path, RecursiveMode::Recursive) {
+                log::error!("Failed to watch source `{}`: {e}", source.label);
+            }
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            sources: sources.to_vec(),
+        })
+    }
+
+    /// Keeps the index updated on a dedicated thread
+    pub fn spawn(
+        self,
+        db: IndexDb,
+        weak_app: Weak<PhotoFlowApp>,
+        on_update: impl Fn(PhotoFlowApp) + Send + Clone + 'static,
+    ) {
+        let result = thread::Builder::new()
+            .name("source-watcher".into())
+            .spawn(move || self.run(db, weak_app, on_update));
+
+        if let Err(e) = result {
+            log::error!("Failed to start source watcher thread: {e}");
+        }
+    }
+
+    fn run(
+        self,
+        mut db: IndexDb,
+        weak_app: Weak<PhotoFlowApp>,
+        on_update: impl Fn(PhotoFlowApp) + Send + Clone + 'static,
+    ) {
+        let mut touched = HashMap::<PathBuf, Instant>::new();
+
+        loop {
+            match self.events.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) if is_change(&event.kind) => {
+                    let now = Instant::now();
+                    touched.extend(event.paths.into_iter().map(|path| (path, now)));
+                }
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(e)) => log::error!("Source watcher error: {e}"),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let settled = take_settled(&mut touched);
+            if settled.is_empty() {
+                continue;
+            }
+
+            let _ = weak_app.upgrade_in_event_loop(|app| app.set_indexing_in_progress(true));
+
+            match update_paths(&mut db, &self.sources, &settled) {
+                Ok(true) => {
+                    log::info!("Index is updated with {} changed paths", settled.len());
+                    let on_update = on_update.clone();
+                    let _ = weak_app.upgrade_in_event_loop(on_update);
+                }
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to update index: {e}"),
+            }
+
+            let _ = weak_app.upgrade_in_event_loop(|app| app.set_indexing_in_progress(false));
+        }
+    }
+}
+
+fn is_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        _ => false,
+    }
+}
+
+fn take_settled(touched: &mut HashMap<PathBuf, Instant>) -> Vec<PathBuf> {
+    let settled = touched
+        .iter()
+        .filter(|(_, time)| time.elapsed() >= SETTLE_TIME)
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+
+    for path in &settled {
+        touched.remove(path);
+    }
+
+    settled
+}
+
+/// Updates the index for the changed paths. Returns `true` if the order table is rebuilt
+fn update_paths(db: &mut IndexDb, sources: &[Source], changed: &[PathBuf]) -> anyhow::Result<bool> {
+    let mut removed = 0;
+    let mut paths = HashMap::new();
+
+    for path in changed {
+        if !path.exists() {
+            if let Some(path_str) = path.to_str() {
+                removed += db.remove_entries(path_str)?;
+            }
+            continue;
+        }
+
+        let Some(source) = find_source(sources, path).filter(|s| is_walked(s, path)) else {
+            continue;
+        };
+
+        if path.is_dir() {
+            super::collect_paths(source, path, &mut paths);
+        } else if is_media_file(path, source) {
+            paths.insert(path.clone(), source);
+        }
+    }
+
+    let mut indexed = HashMap::new();
+    for path in paths.keys().filter_map(|p| p.to_str()) {
+        if let Some(finfo) = db.get_finfo(path)? {
+            indexed.insert(path.to_string(), finfo);
+        }
+    }
+
+    let previews = PreviewCounts::default();
+    let requests = paths
+        .par_iter()
+        .map_init(MetadataParser::new, |parser, (path, source)| {
+            super::worker_parser(parser)
+                .and_then(|parser| super::index_file(path, source, &indexed, parser, &previews))
+                .inspect_err(|e| log::error!("Failed to index file `{}`: {e}", path.display()))
+                .ok()
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+    log::debug!("Loaded previews: {previews}");
+
+    let mut valid_entries = Vec::new();
+    let mut entries = Vec::new();
+    for request in requests {
+        match request {
+            WriteRequest::SetValid(entry) => valid_entries.push(entry),
+            WriteRequest::Upsert(entry) => entries.push(entry),
+        }
+    }
+
+    if removed == 0 && entries.is_empty() {
+        return Ok(false);
+    }
+
+    db.write_batch(&valid_entries, &entries)?;
+    super::pair_live_photos(db)?;
+    db.rebuild_order_table()?;
+
+    Ok(true)
+}
+
+/// Finds the innermost source containing the path
+fn find_source<'a>(sources: &'a [Source], path: &Path) -> Option<&'a Source> {
+    sources
+        .iter()
+        .filter(|source| path.starts_with(&source.path))
+        .max_by_key(|source| source.path.components().count())
+}
+
+/// Checks if the full indexing walk reaches the path. Without `follow_symlinks`
+/// the linked files are indexed, but the linked directories are not walked
+fn is_walked(source: &Source, path: &Path) -> bool {
+    if source.follow_symlinks {
+        return true;
+    }
+
+    let Ok(relative) = path.strip_prefix(&source.path) else {
+        return false;
+    };
+
+    let mut walked = source.path.clone();
+    relative.components().all(|c| {
+        walked.push(c);
+        !walked.is_symlink() || (walked == path && !walked.is_dir())
+    })
+}
+
+fn is_media_file(path: &Path, source: &Source) -> bool {
+    path.is_file() && source.accepts(path) && super::is_media_file(path, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn walks_symlinks_like_full_indexing() {
+        let root = std::env::temp_dir().join(format!("photoflow-{}-watched", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("source/dir")).unwrap();
+        fs::create_dir_all(root.join("outside")).unwrap();
+        fs::write(root.join("outside/a.jpg"), b"").unwrap();
+        symlink(root.join("outside"), root.join("source/linked_dir")).unwrap();
+        symlink(
+            root.join("outside/a.jpg"),
+            root.join("source/dir/linked.jpg"),
+        )
+        .unwrap();
+
+        let config = format!("sources = [{{ path = {:?} }}]", root.join("source"));
+        let mut config: crate::config::Config = toml::from_str(&config).unwrap();
+        let mut source = config.sources.remove(0);
+
+        let walked = |source: &Source, path: &str| is_walked(source, &root.join(path));
+        let checks = [
+            walked(&source, "source/dir/b.jpg"),
+            walked(&source, "source/dir/linked.jpg"),
+            walked(&source, "source/linked_dir"),
+            walked(&source, "source/linked_dir/a.jpg"),
+            walked(&source, "outside/a.jpg"),
+        ];
+        source.follow_symlinks = true;
+        let linked_dir_followed = walked(&source, "source/linked_dir/a.jpg");
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(checks, [true, true, false, false, false]);
+        assert!(linked_dir_followed);
+    }
+}
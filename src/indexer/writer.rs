@@ -0,0 +1,102 @@
+use crate::db::{IndexDb, InsertionEntry, ValidEntry};
+use anyhow::anyhow;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const MAX_BATCH_LEN: usize = 64;
+const MAX_BATCH_AGE: Duration = Duration::from_secs(3);
+
+pub enum WriteRequest {
+    SetValid(ValidEntry),
+    Upsert(InsertionEntry),
+}
+
+/// The only database writer during indexing. Owns its own connection on a dedicated thread
+/// and writes the requests sent by the indexing workers in batched transactions.
+/// Everything committed before an interruption is kept, so the next indexing run skips it
+pub struct IndexWriter {
+    sender: Sender<WriteRequest>,
+    thread: JoinHandle<anyhow::Result<IndexDb>>,
+}
+
+impl IndexWriter {
+    pub fn spawn(db: IndexDb) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("index-writer".into())
+            .spawn(move || write_loop(db, receiver))?;
+
+        Ok(Self { sender, thread })
+    }
+
+    pub fn sender(&self) -> &Sender<WriteRequest> {
+        &self.sender
+    }
+
+    /// Commits all pending requests and gives the connection back
+    pub fn finish(self) -> anyhow::Result<IndexDb> {
+        drop(self.sender);
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("Index writer thread panicked"))?
+    }
+}
+
+fn write_loop(mut db: IndexDb, receiver: Receiver<WriteRequest>) -> anyhow::Result<IndexDb> {
+    let mut batch = Batch::default();
+    let mut last_commit = Instant::now();
+
+    loop {
+        let timeout = MAX_BATCH_AGE.saturating_sub(last_commit.elapsed());
+        let disconnected = match receiver.recv_timeout(timeout) {
+            Ok(request) => {
+                batch.push(request);
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if disconnected || batch.len() >= MAX_BATCH_LEN || last_commit.elapsed() >= MAX_BATCH_AGE {
+            batch.commit(&mut db)?;
+            last_commit = Instant::now();
+        }
+
+        if disconnected {
+            return Ok(db);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Batch {
+    valid_entries: Vec<ValidEntry>,
+    entries: Vec<InsertionEntry>,
+}
+
+impl Batch {
+    fn push(&mut self, request: WriteRequest) {
+        match request {
+            WriteRequest::SetValid(entry) => self.valid_entries.push(entry),
+            WriteRequest::Upsert(entry) => self.entries.push(entry),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.valid_entries.len() + self.entries.len()
+    }
+
+    fn commit(&mut self, db: &mut IndexDb) -> anyhow::Result<()> {
+        if self.len() == 0 {
+            return Ok(());
+        }
+
+        let valid_entries = mem::take(&mut self.valid_entries);
+        let entries = mem::take(&mut self.entries);
+        db.write_batch(&valid_entries, &entries)?;
+
+        Ok(())
+    }
+}
@@ -1,35 +1,34 @@
 mod filter_axis_to_dpad_buttons;
 mod filter_dpad_button_events;
-mod keymap;
 
+use super::{ActionRouter, KeyMap};
+use crate::ui::{Action, ActionKind};
 use crate::winit::WinitWindow;
 use filter_axis_to_dpad_buttons::left_axis_to_dpad_btn;
 use filter_dpad_button_events::filter_wrong_dpad_events;
 use gilrs::ev::filter::{axis_dpad_to_button, deadzone, Jitter, Repeat};
 use gilrs::{Button, EventType, Filter, Gilrs, GilrsBuilder};
-use slint::platform::WindowEvent;
-use slint::{SharedString, Window};
+use slint::Window;
 use std::collections::HashMap;
 use std::time::Duration;
 
-pub use self::keymap::KeyMap;
-
 pub struct GamepadInputListener {
     gilrs: Gilrs,
-    map: HashMap<Button, SharedString>,
+    map: HashMap<Button, Action>,
+    router: ActionRouter,
 }
 
 impl GamepadInputListener {
-    pub fn new(map: KeyMap) -> Result<Self, String> {
+    pub fn new(map: &KeyMap, router: ActionRouter) -> Result<Self, String> {
         let gilrs = GilrsBuilder::new()
             .with_default_filters(false)
             .set_update_state(false)
             .build()
             .map_err(|error| format!("Failed to init gamepad input backend: {error}"))?;
 
-        let map = map.into();
+        let map = map.buttons().clone();
 
-        Ok(Self { gilrs, map })
+        Ok(Self { gilrs, map, router })
     }
 
     pub fn poll(&mut self, window: &Window) {
@@ -53,23 +52,15 @@ impl GamepadInputListener {
         {
             gilrs.update(&event);
 
-            match event.event {
-                EventType::ButtonPressed(btn, _) if has_focus => {
-                    if let Some(key) = self.map.get(&btn) {
-                        window.dispatch_event(WindowEvent::KeyPressed { text: key.clone() });
-                    }
-                }
-                EventType::ButtonRepeated(btn, _) if has_focus => {
-                    if let Some(key) = self.map.get(&btn) {
-                        window.dispatch_event(WindowEvent::KeyPressRepeated { text: key.clone() });
-                    }
-                }
-                EventType::ButtonReleased(btn, _) if has_focus => {
-                    if let Some(key) = self.map.get(&btn) {
-                        window.dispatch_event(WindowEvent::KeyReleased { text: key.clone() });
-                    }
-                }
+            let (btn, kind) = match event.event {
+                EventType::ButtonPressed(btn, _) if has_focus => (btn, ActionKind::Pressed),
+                EventType::ButtonRepeated(btn, _) if has_focus => (btn, ActionKind::Repeated),
+                EventType::ButtonReleased(btn, _) if has_focus => (btn, ActionKind::Released),
                 _ => continue,
+            };
+
+            if let Some(action) = self.map.get(&btn) {
+                self.router.dispatch(*action, kind);
             }
         }
     }
@@ -0,0 +1,313 @@
+use crate::config::ControlBinding;
+use crate::ui::Action;
+use anyhow::{anyhow, bail};
+use gilrs::Button;
+use slint::SharedString;
+use slint::platform::Key;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+
+impl Action {
+    pub const ALL: [Action; 11] = [
+        Action::Prev,
+        Action::Next,
+        Action::Up,
+        Action::Down,
+        Action::PrevGroup,
+        Action::NextGroup,
+        Action::Select,
+        Action::Back,
+        Action::Menu,
+        Action::SeekBackward,
+        Action::SeekForward,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Prev => "prev",
+            Action::Next => "next",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::PrevGroup => "prev_group",
+            Action::NextGroup => "next_group",
+            Action::Select => "select",
+            Action::Back => "back",
+            Action::Menu => "menu",
+            Action::SeekBackward => "seek_backward",
+            Action::SeekForward => "seek_forward",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+const DEFAULT_BINDINGS: [(Action, Button, Key); 11] = [
+    (Action::Prev, Button::DPadLeft, Key::LeftArrow),
+    (Action::Next, Button::DPadRight, Key::RightArrow),
+    (Action::Up, Button::DPadUp, Key::UpArrow),
+    (Action::Down, Button::DPadDown, Key::DownArrow),
+    (Action::PrevGroup, Button::LeftTrigger, Key::PageUp),
+    (Action::NextGroup, Button::RightTrigger, Key::PageDown),
+    (Action::Select, Button::South, Key::Return),
+    (Action::Back, Button::East, Key::Escape),
+    (Action::Menu, Button::Start, Key::Menu),
+    (Action::SeekBackward, Button::LeftTrigger2, Key::Home),
+    (Action::SeekForward, Button::RightTrigger2, Key::End),
+];
+
+/// Bindings of the gamepad buttons and keyboard keys to the actions
+#[derive(Clone)]
+pub struct KeyMap {
+    buttons: HashMap<Button, Action>,
+    keys: HashMap<SharedString, Action>,
+}
+
+impl KeyMap {
+    /// Builds the map from the `[controls]` config section.
+    /// The configured bindings replace the default ones of the same action. An input can't be
+    /// taken from the default bindings of another action unless that action is rebound too
+    pub fn new(controls: &BTreeMap<String, ControlBinding>) -> anyhow::Result<Self> {
+        let mut buttons = HashMap::new();
+        let mut keys = HashMap::new();
+        let mut rebound_buttons = HashSet::new();
+        let mut rebound_keys = HashSet::new();
+
+        for (name, binding) in controls {
+            let action = Action::from_name(name).ok_or_else(|| {
+                let names = Action::ALL.map(|a| a.name()).join("`, `");
+                anyhow!("Unknown action `{name}`, expected one of `{names}`")
+            })?;
+
+            if let Some(button_names) = &binding.gamepad {
+                rebound_buttons.insert(action.name());
+                for button_name in button_names {
+                    let button = parse_button(button_name)
+                        .ok_or_else(|| anyhow!("Unknown gamepad button `{button_name}`"))?;
+                    bind(&mut buttons, button, action, || {
+                        format!("Gamepad button `{button_name}`")
+                    })?;
+                }
+            }
+
+            if let Some(key_names) = &binding.keyboard {
+                rebound_keys.insert(action.name());
+                for key_name in key_names {
+                    let key = parse_key(key_name)
+                        .ok_or_else(|| anyhow!("Unknown keyboard key `{key_name}`"))?;
+                    bind(&mut keys, key, action, || {
+                        format!("Keyboard key `{key_name}`")
+                    })?;
+                }
+            }
+        }
+
+        for (action, button, key) in DEFAULT_BINDINGS {
+            if !rebound_buttons.contains(action.name()) {
+                bind_default(&mut buttons, button, action, "gamepad")?;
+            }
+            if !rebound_keys.contains(action.name()) {
+                bind_default(&mut keys, key.into(), action, "keyboard")?;
+            }
+        }
+
+        Ok(Self { buttons, keys })
+    }
+
+    pub fn buttons(&self) -> &HashMap<Button, Action> {
+        &self.buttons
+    }
+
+    pub fn action_of_key(&self, text: &str) -> Option<Action> {
+        self.keys.get(text).copied()
+    }
+}
+
+fn bind<T: Eq + Hash>(
+    map: &mut HashMap<T, Action>,
+    input: T,
+    action: Action,
+    input_name: impl FnOnce() -> String,
+) -> anyhow::Result<()> {
+    match map.insert(input, action) {
+        Some(other) if other != action => bail!(
+            "{} is bound to both `{}` and `{}` actions",
+            input_name(),
+            other.name(),
+            action.name()
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn bind_default<T: Eq + Hash>(
+    map: &mut HashMap<T, Action>,
+    input: T,
+    action: Action,
+    input_kind: &str,
+) -> anyhow::Result<()> {
+    match map.get(&input) {
+        Some(other) if *other != action => bail!(
+            "The `{}` action takes the default {input_kind} binding of `{}`, \
+            rebind `{}` as well (an empty list removes its bindings)",
+            other.name(),
+            action.name(),
+            action.name()
+        ),
+        Some(_) => Ok(()),
+        None => {
+            map.insert(input, action);
+            Ok(())
+        }
+    }
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    let button = match name {
+        "South" => Button::South,
+        "East" => Button::East,
+        "North" => Button::North,
+        "West" => Button::West,
+        "C" => Button::C,
+        "Z" => Button::Z,
+        "LeftTrigger" => Button::LeftTrigger,
+        "LeftTrigger2" => Button::LeftTrigger2,
+        "RightTrigger" => Button::RightTrigger,
+        "RightTrigger2" => Button::RightTrigger2,
+        "Select" => Button::Select,
+        "Start" => Button::Start,
+        "Mode" => Button::Mode,
+        "LeftThumb" => Button::LeftThumb,
+        "RightThumb" => Button::RightThumb,
+        "DPadUp" => Button::DPadUp,
+        "DPadDown" => Button::DPadDown,
+        "DPadLeft" => Button::DPadLeft,
+        "DPadRight" => Button::DPadRight,
+        _ => return None,
+    };
+
+    Some(button)
+}
+
+/// Parses a special key name or a single character key
+fn parse_key(name: &str) -> Option<SharedString> {
+    let key = match name {
+        "Backspace" => Key::Backspace,
+        "Tab" => Key::Tab,
+        "Return" | "Enter" => Key::Return,
+        "Escape" => Key::Escape,
+        "Delete" => Key::Delete,
+        "Space" => Key::Space,
+        "Up" => Key::UpArrow,
+        "Down" => Key::DownArrow,
+        "Left" => Key::LeftArrow,
+        "Right" => Key::RightArrow,
+        "Insert" => Key::Insert,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Menu" => Key::Menu,
+        "F1" => Key::F1,
+        "F2" => Key::F2,
+        "F3" => Key::F3,
+        "F4" => Key::F4,
+        "F5" => Key::F5,
+        "F6" => Key::F6,
+        "F7" => Key::F7,
+        "F8" => Key::F8,
+        "F9" => Key::F9,
+        "F10" => Key::F10,
+        "F11" => Key::F11,
+        "F12" => Key::F12,
+        _ => {
+            let mut chars = name.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_control() => Some(c.to_string().into()),
+                _ => None,
+            };
+        }
+    };
+
+    Some(key.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controls(toml: &str) -> BTreeMap<String, ControlBinding> {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn error(controls: &BTreeMap<String, ControlBinding>) -> String {
+        KeyMap::new(controls)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn keeps_default_bindings() {
+        let map = KeyMap::new(&BTreeMap::new()).unwrap();
+        assert_eq!(map.buttons.get(&Button::South), Some(&Action::Select));
+        assert_eq!(
+            map.keys.get(SharedString::from(Key::Escape).as_str()),
+            Some(&Action::Back)
+        );
+        assert_eq!(map.buttons.len(), DEFAULT_BINDINGS.len());
+    }
+
+    #[test]
+    fn replaces_default_bindings_of_action() {
+        let map = KeyMap::new(&controls(
+            r#"select = { gamepad = ["North", "West"], keyboard = ["x"] }"#,
+        ));
+        let map = map.unwrap();
+
+        assert_eq!(map.buttons.get(&Button::South), None);
+        assert_eq!(map.buttons.get(&Button::North), Some(&Action::Select));
+        assert_eq!(map.buttons.get(&Button::West), Some(&Action::Select));
+        assert_eq!(map.keys.get("x"), Some(&Action::Select));
+        assert_eq!(map.keys.get(SharedString::from(Key::Return).as_str()), None);
+    }
+
+    #[test]
+    fn moves_inputs_between_rebound_actions() {
+        let map = KeyMap::new(&controls(
+            r#"
+            next = { gamepad = ["South"] }
+            select = { gamepad = [] }
+            "#,
+        ));
+        let map = map.unwrap();
+
+        assert_eq!(map.buttons.get(&Button::South), Some(&Action::Next));
+        assert_eq!(map.buttons.get(&Button::DPadRight), None);
+    }
+
+    #[test]
+    fn rejects_invalid_bindings() {
+        assert!(error(&controls("jump = {}")).contains("Unknown action `jump`"));
+        assert!(error(&controls(r#"next = { gamepad = ["Y"] }"#)).contains("`Y`"));
+        assert!(error(&controls(r#"next = { keyboard = ["Ctrl"] }"#)).contains("`Ctrl`"));
+
+        let conflict = controls(
+            r#"
+            next = { gamepad = ["North"] }
+            prev = { gamepad = ["North"] }
+            "#,
+        );
+        assert!(error(&conflict).contains("bound to both `next` and `prev`"));
+    }
+
+    #[test]
+    fn rejects_taking_default_bindings() {
+        let message = error(&controls(r#"next = { gamepad = ["South"] }"#));
+        assert!(message.contains("`next` action takes the default gamepad binding of `select`"));
+
+        let message = error(&controls(r#"select = { keyboard = ["Escape"] }"#));
+        assert!(message.contains("`select` action takes the default keyboard binding of `back`"));
+    }
+}
@@ -0,0 +1,38 @@
+mod gamepad;
+mod keymap;
+
+use crate::ui::{Action, ActionKind, PhotoFlowApp};
+use slint::{ComponentHandle, Weak};
+
+pub use self::gamepad::GamepadInputListener;
+pub use self::keymap::KeyMap;
+
+/// Delivers the actions produced by all input sources to the active screen
+#[derive(Clone)]
+pub struct ActionRouter {
+    weak_app: Weak<PhotoFlowApp>,
+}
+
+impl ActionRouter {
+    pub fn new(app: &PhotoFlowApp) -> Self {
+        Self {
+            weak_app: app.as_weak(),
+        }
+    }
+
+    /// Returns `true` if the action is consumed. Should be called on the event loop thread
+    pub fn dispatch(&self, action: Action, kind: ActionKind) -> bool {
+        self.weak_app
+            .upgrade()
+            .map(|app| app.invoke_handle_action(action, kind))
+            .unwrap_or_default()
+    }
+}
+
+/// Turns the key events of the window into actions
+pub fn bind_keyboard(app: &PhotoFlowApp, router: ActionRouter, map: KeyMap) {
+    app.on_key_event(move |text, kind| match map.action_of_key(&text) {
+        Some(action) => router.dispatch(action, kind),
+        None => false,
+    });
+}
@@ -1,20 +1,23 @@
+use crate::cli::Args;
 use crate::config::Config;
 use crate::db::IndexDb;
-use crate::gamepad_input::{GamepadInputListener, KeyMap};
-use crate::ui::{GamepadKey, Mode, PhotoFlowApp};
+use crate::input::{ActionRouter, GamepadInputListener, KeyMap};
+use crate::ui::{Mode, PhotoFlowApp};
 use crate::winit::WinitWindow;
 use anyhow::anyhow;
+use clap::Parser;
 use slint::{ComponentHandle, Timer, TimerMode};
-use std::fs;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+mod cli;
 mod config;
 mod db;
 mod exif_orientation;
-mod gamepad_input;
 mod image_loader;
 mod indexer;
+mod input;
 mod media;
 mod util;
 mod video;
@@ -28,50 +31,95 @@ pub mod ui {
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
+    let args = Args::parse();
     let xdg_dirs = xdg::BaseDirectories::new();
 
-    let cfg_path = xdg_dirs
-        .get_config_file("photoflow.toml")
-        .ok_or_else(|| anyhow!("Failed to get config file path"))?;
-    let db_path = xdg_dirs
-        .get_data_file("photoflow.db")
-        .ok_or_else(|| anyhow!("Failed to get database file path"))?;
+    let cfg_path = match args.config {
+        Some(path) => path,
+        None => xdg_dirs
+            .get_config_file("photoflow.toml")
+            .ok_or_else(|| anyhow!("Failed to get config file path"))?,
+    };
+    let db_path = match args.db {
+        Some(path) => path,
+        None => xdg_dirs
+            .get_data_file("photoflow.db")
+            .ok_or_else(|| anyhow!("Failed to get database file path"))?,
+    };
+
+    match args.command {
+        Some(command) => cli::run(command, &cfg_path, &db_path),
+        None => run_app(&cfg_path, &db_path),
+    }
+}
 
-    let config = fs::read_to_string(&cfg_path)
-        .map_err(|e| anyhow!("Failed to read config file `{}`: {e}", cfg_path.display()))?;
-    let config = toml::from_str::<Config>(&config)
-        .map_err(|e| anyhow!("Failed to parse config file `{}`: {e}", cfg_path.display()))?;
+fn run_app(cfg_path: &Path, db_path: &Path) -> anyhow::Result<()> {
+    let config = Config::load(cfg_path)?;
+    let key_map = KeyMap::new(&config.controls)
+        .map_err(|e| anyhow!("Invalid controls in config `{}`: {e}", cfg_path.display()))?;
 
-    let db = IndexDb::open(&db_path)
+    let db = IndexDb::open(db_path)
+        .map_err(|e| anyhow!("Failed to open database file `{}`: {e}", db_path.display()))?;
+    let read_db = IndexDb::open_read_only(db_path)
+        .map_err(|e| anyhow!("Failed to open database file `{}`: {e}", db_path.display()))?;
+    let read_db = Arc::new(Mutex::new(read_db));
+    let sort_db = IndexDb::open(db_path)
         .map_err(|e| anyhow!("Failed to open database file `{}`: {e}", db_path.display()))?;
-    let db = Arc::new(Mutex::new(db));
 
     gstreamer::init()?;
 
     let app = PhotoFlowApp::new()?;
     setup_app_window(&app);
-    let _gamepad_poll_timer = setup_gamepad_input(&app);
-
-    viewer::bind_media_viewer(&app, db.clone());
+    let _gamepad_poll_timer = setup_input(&app, key_map);
+
+    viewer::bind_media_viewer(&app, read_db.clone())?;
+    viewer::bind_sort_menu(&app, &read_db, sort_db)?;
+
+    // Show the previously indexed media right away and update the index in the background
+    let gallery_config = config.gallery;
+    let indexed_count = read_db.lock().unwrap().get_item_count().unwrap_or_default();
+    let has_index = indexed_count > 0;
+    if has_index {
+        log::info!("Indexed media files count: {indexed_count}");
+        viewer::bind_gallery_models(&app, read_db.clone(), gallery_config)?;
+        app.set_mode(Mode::Gallery);
+    } else {
+        app.set_mode(Mode::PreIndexing);
+    }
 
     log::info!("Evaluating media files count...");
-    app.set_mode(Mode::PreIndexing);
     indexer::update_index_bg(
         config.sources,
-        db.clone(),
+        db,
         app.as_weak(),
         move |app, count| {
             log::info!("Media files count: {count}. Start indexing...");
             app.set_indexing_total(count);
-            app.set_mode(Mode::Indexing);
+            if app.get_mode() == Mode::PreIndexing {
+                app.set_mode(Mode::Indexing);
+            }
         },
         move |app| {
             log::info!("Indexing finished!");
-            if let Err(e) = viewer::bind_gallery_models(&app, db) {
+            app.set_indexing_in_progress(false);
+
+            if has_index {
+                if let Err(e) = viewer::refresh_gallery_models(&app) {
+                    log::error!("Failed to refresh gallery models: {e}");
+                }
+                return;
+            }
+
+            if let Err(e) = viewer::bind_gallery_models(&app, read_db, gallery_config) {
                 log::error!("Failed to bind gallery models: {e}");
             }
             app.set_mode(Mode::Gallery);
         },
+        move |app| {
+            if let Err(e) = viewer::refresh_gallery_models(&app) {
+                log::error!("Failed to refresh gallery models: {e}");
+            }
+        },
     );
 
     app.run()?;
@@ -96,15 +144,10 @@ fn setup_app_window(app: &PhotoFlowApp) {
     });
 }
 
-fn setup_gamepad_input(app: &PhotoFlowApp) -> Timer {
-    let map = KeyMap::default();
-    let mut gamepad_manager = GamepadInputListener::new(map.clone()).unwrap();
-
-    let ui_map = app.global::<GamepadKey>();
-    ui_map.invoke_set_actions(map.act_up, map.act_right, map.act_down, map.act_left);
-    ui_map.invoke_set_dpad(map.dpad_up, map.dpad_right, map.dpad_down, map.dpad_left);
-    ui_map.invoke_set_menupad(map.menu_main, map.menu_left, map.menu_right);
-    ui_map.invoke_set_triggers(map.trig_l1, map.trig_l2, map.trig_r1, map.trig_r2);
+fn setup_input(app: &PhotoFlowApp, map: KeyMap) -> Timer {
+    let router = ActionRouter::new(app);
+    let mut gamepad_manager = GamepadInputListener::new(&map, router.clone()).unwrap();
+    input::bind_keyboard(app, router, map);
 
     let app_weak = app.as_weak();
     let gamepad_poll_timer = Timer::default();
@@ -0,0 +1,261 @@
+use super::tiff::{Ifd, Tiff};
+use crate::exif_orientation::ExifOrientation;
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+
+/// Tags of a raw EXIF chunk extracted from a container by the image decoders
+#[derive(Default)]
+pub struct ExifChunk {
+    pub datetime: Option<DateTime<FixedOffset>>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub orientation: Option<ExifOrientation>,
+    /// Links the still and the motion parts of an Apple Live Photo
+    pub content_id: Option<String>,
+}
+
+const TAG_IMAGE_WIDTH: u16 = 0x0100;
+const TAG_IMAGE_HEIGHT: u16 = 0x0101;
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_MAKER_NOTE: u16 = 0x927C;
+const TAG_EXIF_IMAGE_WIDTH: u16 = 0xA002;
+const TAG_EXIF_IMAGE_HEIGHT: u16 = 0xA003;
+
+const APPLE_MAKER_NOTE_PREFIX: &[u8] = b"Apple iOS\0";
+const APPLE_MAKER_NOTE_IFD_OFFSET: usize = 14;
+const TAG_APPLE_CONTENT_ID: u16 = 0x0011;
+
+impl ExifChunk {
+    /// Accepts a TIFF structure with an optional `Exif\0\0` prefix
+    pub fn parse(chunk: &[u8]) -> Option<ExifChunk> {
+        let chunk = chunk.strip_prefix(b"Exif\0\0").unwrap_or(chunk);
+        let ifd0 = Tiff::new(chunk)?.first_ifd()?;
+        let exif_ifd = ifd0.sub_ifd(TAG_EXIF_IFD);
+
+        let exif_u32 = |tag| exif_ifd.and_then(|ifd| ifd.entry(tag)?.as_u32());
+        let exif_str = |tag| exif_ifd.and_then(|ifd| ifd.entry(tag)?.as_str());
+
+        Some(ExifChunk {
+            datetime: exif_str(TAG_DATE_TIME_ORIGINAL)
+                .or_else(|| ifd_str(&ifd0, TAG_DATE_TIME))
+                .and_then(|dt| parse_datetime(dt, exif_str(TAG_OFFSET_TIME_ORIGINAL))),
+            width: exif_u32(TAG_EXIF_IMAGE_WIDTH)
+                .or_else(|| ifd0.entry(TAG_IMAGE_WIDTH)?.as_u32())
+                .filter(|w| *w > 0),
+            height: exif_u32(TAG_EXIF_IMAGE_HEIGHT)
+                .or_else(|| ifd0.entry(TAG_IMAGE_HEIGHT)?.as_u32())
+                .filter(|h| *h > 0),
+            camera_make: ifd_str(&ifd0, TAG_MAKE).map(String::from),
+            camera_model: ifd_str(&ifd0, TAG_MODEL).map(String::from),
+            orientation: ifd0
+                .entry(TAG_ORIENTATION)
+                .and_then(|e| e.as_u32())
+                .and_then(|u| i64::from(u).try_into().ok()),
+            content_id: exif_ifd
+                .and_then(|ifd| ifd.entry(TAG_MAKER_NOTE))
+                .and_then(|e| apple_content_id(e.as_bytes()))
+                .map(String::from),
+        })
+    }
+
+    /// HEIF EXIF blocks start with a big-endian offset to the TIFF header
+    pub fn parse_heif_block(block: &[u8]) -> Option<ExifChunk> {
+        let offset = u32::from_be_bytes(block.get(..4)?.try_into().ok()?) as usize;
+        ExifChunk::parse(block.get(4 + offset..)?)
+    }
+}
+
+/// Returns the JPEG thumbnail referenced by IFD1 of the EXIF chunk
+pub fn exif_thumbnail(chunk: &[u8]) -> Option<&[u8]> {
+    let chunk = chunk.strip_prefix(b"Exif\0\0").unwrap_or(chunk);
+    let tiff = Tiff::new(chunk)?;
+    let ifd1 = tiff.first_ifd()?.next()?;
+
+    let offset = ifd1.entry(TAG_JPEG_OFFSET)?.as_u32()?;
+    let length = ifd1.entry(TAG_JPEG_LENGTH)?.as_u32()?;
+    tiff.bytes(offset, length)
+}
+
+fn ifd_str<'a>(ifd: &Ifd<'a>, tag: u16) -> Option<&'a str> {
+    ifd.entry(tag)?.as_str()
+}
+
+/// The Apple maker note is a big-endian IFD with the offsets relative to the maker note start
+fn apple_content_id(maker_note: &[u8]) -> Option<&str> {
+    if !maker_note.starts_with(APPLE_MAKER_NOTE_PREFIX) {
+        return None;
+    }
+
+    let ifd = Tiff::headless(maker_note, true).ifd_at(APPLE_MAKER_NOTE_IFD_OFFSET)?;
+    ifd_str(&ifd, TAG_APPLE_CONTENT_ID)
+}
+
+/// A date without the offset tag is treated as UTC
+fn parse_datetime(value: &str, offset: Option<&str>) -> Option<DateTime<FixedOffset>> {
+    let naive = NaiveDateTime::parse_from_str(value, "%Y:%m:%d %H:%M:%S").ok()?;
+    match offset.and_then(|o| o.parse::<FixedOffset>().ok()) {
+        Some(offset) => naive.and_local_timezone(offset).single(),
+        None => Some(naive.and_utc().fixed_offset()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::tiff_writer::TiffWriter;
+
+    const CONTENT_ID: &str = "0C1A5E7B-2F64-4A7D-9E3B-7D2C1B5A9F80";
+
+    fn apple_maker_note() -> Vec<u8> {
+        let mut w = TiffWriter::headless(b"Apple iOS\0\0\x01MM", true);
+        let entries = vec![w.ascii(TAG_APPLE_CONTENT_ID, CONTENT_ID)];
+        w.ifd(entries, 0);
+        w.into_bytes()
+    }
+
+    fn exif_chunk() -> Vec<u8> {
+        let mut w = TiffWriter::new(false);
+        let thumbnail = w.blob(b"\xFF\xD8thumbnail\xFF\xD9");
+        let entries = vec![
+            w.long(TAG_JPEG_OFFSET, &[thumbnail]),
+            w.long(TAG_JPEG_LENGTH, &[13]),
+        ];
+        let ifd1 = w.ifd(entries, 0);
+        let entries = vec![
+            w.ascii(TAG_DATE_TIME_ORIGINAL, "2024:06:01 12:30:00"),
+            w.ascii(TAG_OFFSET_TIME_ORIGINAL, "+02:00"),
+            w.undefined(TAG_MAKER_NOTE, &apple_maker_note()),
+            w.long(TAG_EXIF_IMAGE_WIDTH, &[4032]),
+            w.short(TAG_EXIF_IMAGE_HEIGHT, &[3024]),
+        ];
+        let exif_ifd = w.ifd(entries, 0);
+        let entries = vec![
+            w.ascii(TAG_MAKE, "Apple"),
+            w.ascii(TAG_MODEL, "iPhone 15"),
+            w.short(TAG_ORIENTATION, &[6]),
+            w.ascii(TAG_DATE_TIME, "2024:06:02 08:00:00"),
+            w.long(TAG_EXIF_IFD, &[exif_ifd]),
+        ];
+        let ifd0 = w.ifd(entries, ifd1);
+        w.finish(ifd0)
+    }
+
+    #[test]
+    fn parses_tags() {
+        let exif = ExifChunk::parse(&exif_chunk()).unwrap();
+
+        let datetime = exif.datetime.unwrap();
+        assert_eq!(datetime.to_rfc3339(), "2024-06-01T12:30:00+02:00");
+        assert_eq!(exif.width, Some(4032));
+        assert_eq!(exif.height, Some(3024));
+        assert_eq!(exif.camera_make.as_deref(), Some("Apple"));
+        assert_eq!(exif.camera_model.as_deref(), Some("iPhone 15"));
+        assert_eq!(exif.orientation.map(i64::from), Some(6));
+        assert_eq!(exif.content_id.as_deref(), Some(CONTENT_ID));
+    }
+
+    #[test]
+    fn falls_back_to_ifd0_tags() {
+        let mut w = TiffWriter::new(true);
+        let entries = vec![
+            w.long(TAG_IMAGE_WIDTH, &[640]),
+            w.long(TAG_IMAGE_HEIGHT, &[0]),
+            w.short(TAG_ORIENTATION, &[9]),
+            w.ascii(TAG_DATE_TIME, "2024:06:02 08:00:00"),
+        ];
+        let ifd0 = w.ifd(entries, 0);
+        let exif = ExifChunk::parse(&w.finish(ifd0)).unwrap();
+
+        let datetime = exif.datetime.unwrap();
+        assert_eq!(datetime.to_rfc3339(), "2024-06-02T08:00:00+00:00");
+        assert_eq!(exif.width, Some(640));
+        assert_eq!(exif.height, None);
+        assert!(exif.orientation.is_none());
+        assert!(exif.content_id.is_none());
+    }
+
+    #[test]
+    fn accepts_exif_prefix_and_heif_block() {
+        let chunk = exif_chunk();
+
+        let mut prefixed = b"Exif\0\0".to_vec();
+        prefixed.extend(&chunk);
+        assert!(ExifChunk::parse(&prefixed).unwrap().datetime.is_some());
+
+        let mut block = 6u32.to_be_bytes().to_vec();
+        block.extend(&prefixed);
+        assert!(
+            ExifChunk::parse_heif_block(&block)
+                .unwrap()
+                .datetime
+                .is_some()
+        );
+
+        assert!(ExifChunk::parse_heif_block(&[0, 0]).is_none());
+        assert!(ExifChunk::parse_heif_block(&[0xFF; 8]).is_none());
+    }
+
+    #[test]
+    fn ignores_malformed_values() {
+        let mut w = TiffWriter::new(false);
+        let entries = vec![
+            w.ascii(TAG_DATE_TIME_ORIGINAL, "2024-06-01 12:30"),
+            w.ascii(TAG_OFFSET_TIME_ORIGINAL, "local"),
+            w.undefined(TAG_MAKER_NOTE, b"Apple iOS\0\0\x01MM\xFF\xFF"),
+        ];
+        let exif_ifd = w.ifd(entries, 0);
+        let entries = vec![
+            w.short(TAG_MAKE, &[1]),
+            w.long(TAG_EXIF_IFD, &[exif_ifd]),
+            w.ascii(TAG_DATE_TIME, "2024:06:02 08:00:00"),
+        ];
+        let ifd0 = w.ifd(entries, 0);
+        let exif = ExifChunk::parse(&w.finish(ifd0)).unwrap();
+
+        assert!(exif.datetime.is_none());
+        assert!(exif.camera_make.is_none());
+        assert!(exif.content_id.is_none());
+        assert!(apple_content_id(b"Nikon\0").is_none());
+        assert!(apple_content_id(&apple_maker_note()[..20]).is_none());
+    }
+
+    #[test]
+    fn truncated_chunks_do_not_panic() {
+        let chunk = exif_chunk();
+        for len in 0..chunk.len() {
+            ExifChunk::parse(&chunk[..len]);
+            exif_thumbnail(&chunk[..len]);
+        }
+
+        assert!(ExifChunk::parse(&chunk[..7]).is_none());
+        assert!(ExifChunk::parse(b"not an exif chunk").is_none());
+    }
+
+    #[test]
+    fn finds_thumbnail() {
+        let chunk = exif_chunk();
+        assert_eq!(
+            exif_thumbnail(&chunk),
+            Some(&b"\xFF\xD8thumbnail\xFF\xD9"[..])
+        );
+
+        let mut w = TiffWriter::new(false);
+        let entries = vec![
+            w.long(TAG_JPEG_OFFSET, &[8]),
+            w.long(TAG_JPEG_LENGTH, &[1000]),
+        ];
+        let ifd1 = w.ifd(entries, 0);
+        let entries = vec![w.ascii(TAG_MAKE, "Camera")];
+        let ifd0 = w.ifd(entries, ifd1);
+        assert!(exif_thumbnail(&w.finish(ifd0)).is_none());
+    }
+}
@@ -0,0 +1,278 @@
+use super::exif_chunk::ExifChunk;
+use super::{ImageType, MediaType, VideoType};
+use image::{ImageDecoder, ImageReader};
+use libheif_rs::HeifContext;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::iter;
+use std::path::Path;
+
+const CONTENT_ID_KEY: &[u8] = b"com.apple.quicktime.content.identifier";
+
+/// The `moov` box of a Live Photo video is small. Bigger ones are not read into memory
+const MAX_MOOV_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Reads the content identifier that links the still and the motion parts of an Apple Live Photo.
+/// Stills keep it in the Apple maker note, videos keep it in the QuickTime metadata
+pub fn live_photo_content_id(path: &Path, media_type: &MediaType) -> Option<String> {
+    match media_type {
+        MediaType::Image(ImageType::Jpeg) => jpeg_content_id(path),
+        MediaType::Image(ImageType::Heic) => heic_content_id(path),
+        MediaType::Video(VideoType::Mov) => quicktime_content_id(path),
+        _ => None,
+    }
+}
+
+fn jpeg_content_id(path: &Path) -> Option<String> {
+    let mut decoder = ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+
+    let chunk = decoder.exif_metadata().ok()??;
+    ExifChunk::parse(&chunk)?.content_id
+}
+
+fn heic_content_id(path: &Path) -> Option<String> {
+    let ctx = HeifContext::read_from_file(path.to_str()?).ok()?;
+    let handle = ctx.primary_image_handle().ok()?;
+    handle
+        .metadata_block_ids(b"Exif")
+        .into_iter()
+        .find_map(|id| handle.metadata(id).ok())
+        .and_then(|block| ExifChunk::parse_heif_block(&block))?
+        .content_id
+}
+
+/// The identifier is stored as an `mdta` key of `moov/meta` and its value is the `ilst` item
+/// with the type that equals to the 1-based key index
+fn quicktime_content_id(path: &Path) -> Option<String> {
+    let moov = read_moov(&mut File::open(path).ok()?)?;
+
+    let mut meta = find_box(&moov, b"meta")?;
+    // ISO `meta` is a full box, QuickTime one is not
+    if meta.get(4..8) != Some(b"hdlr") {
+        meta = meta.get(4..)?;
+    }
+
+    let key_idx = key_index(find_box(meta, b"keys")?, CONTENT_ID_KEY)?;
+    let item = find_box(find_box(meta, b"ilst")?, &key_idx.to_be_bytes())?;
+
+    // The data box starts with the type indicator and the locale
+    let value = find_box(item, b"data")?.get(8..)?;
+    std::str::from_utf8(value)
+        .ok()
+        .map(|s| s.trim_matches(char::from(0)).trim())
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+}
+
+/// Skips top-level boxes without reading them, the `moov` box may be located after the media data
+fn read_moov(file: &mut File) -> Option<Vec<u8>> {
+    let file_len = file.metadata().ok()?.len();
+    let mut pos = 0;
+
+    while pos + 8 <= file_len {
+        file.seek(SeekFrom::Start(pos)).ok()?;
+
+        let mut header = [0; 8];
+        file.read_exact(&mut header).ok()?;
+
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().ok()?) {
+            0 => (8, file_len - pos),
+            1 => {
+                let mut large_size = [0; 8];
+                file.read_exact(&mut large_size).ok()?;
+                (16, u64::from_be_bytes(large_size))
+            }
+            size => (8, u64::from(size)),
+        };
+
+        if size < header_len {
+            return None;
+        }
+
+        if &header[4..] == b"moov" {
+            if size > MAX_MOOV_SIZE {
+                return None;
+            }
+
+            let mut payload = vec![0; (size - header_len) as usize];
+            file.read_exact(&mut payload).ok()?;
+            return Some(payload);
+        }
+
+        pos += size;
+    }
+
+    None
+}
+
+fn find_box<'a>(data: &'a [u8], box_type: &[u8]) -> Option<&'a [u8]> {
+    boxes(data).find_map(|(t, payload)| (t == box_type).then_some(payload))
+}
+
+/// Iterates over the type and the payload of boxes with 32-bit sizes
+fn boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 0;
+    iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let box_type = data.get(pos + 4..pos + 8)?;
+        let payload = data.get(pos + 8..pos.checked_add(size)?)?;
+        pos += size.max(8);
+        Some((box_type, payload))
+    })
+}
+
+/// Returns the 1-based index of the key in the `keys` full box
+fn key_index(keys: &[u8], key: &[u8]) -> Option<u32> {
+    let mut pos = 8;
+    let count = u32::from_be_bytes(keys.get(4..8)?.try_into().ok()?);
+
+    for idx in 1..=count {
+        let size = u32::from_be_bytes(keys.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        // The key size includes the size and the namespace fields
+        if keys.get(pos + 8..pos.checked_add(size)?)? == key {
+            return Some(idx);
+        }
+        pos += size.max(8);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CONTENT_ID: &str = "0C1A5E7B-2F64-4A7D-9E3B-7D2C1B5A9F80";
+
+    fn mp4_box(box_type: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((8 + payload.len()) as u32).to_be_bytes().to_vec();
+        data.extend(box_type);
+        data.extend(payload);
+        data
+    }
+
+    fn moov(full_box_meta: bool) -> Vec<u8> {
+        let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        for key in [&b"com.apple.quicktime.make"[..], CONTENT_ID_KEY] {
+            keys.extend(((8 + key.len()) as u32).to_be_bytes());
+            keys.extend(b"mdta");
+            keys.extend(key);
+        }
+
+        let mut value = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        value.extend(CONTENT_ID.as_bytes());
+        let item = mp4_box(&2u32.to_be_bytes(), &mp4_box(b"data", &value));
+
+        let mut meta = if full_box_meta {
+            vec![0; 4]
+        } else {
+            Vec::new()
+        };
+        meta.extend(mp4_box(b"hdlr", &[0; 24]));
+        meta.extend(mp4_box(b"keys", &keys));
+        meta.extend(mp4_box(b"ilst", &item));
+
+        let mut moov = mp4_box(b"mvhd", &[0; 100]);
+        moov.extend(mp4_box(b"meta", &meta));
+        mp4_box(b"moov", &moov)
+    }
+
+    fn video_file(full_box_meta: bool) -> Vec<u8> {
+        let mut data = mp4_box(b"ftyp", b"qt  \0\0\0\0qt  ");
+        // A 64-bit sized media data box located before the movie box
+        data.extend(1u32.to_be_bytes());
+        data.extend(b"mdat");
+        data.extend(32u64.to_be_bytes());
+        data.extend([0; 16]);
+        data.extend(moov(full_box_meta));
+        data
+    }
+
+    fn write_temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("photoflow-{}-{name}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_quicktime_content_id() {
+        for full_box_meta in [false, true] {
+            let path = write_temp_file("live.mov", &video_file(full_box_meta));
+            let content_id = quicktime_content_id(&path);
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(content_id.as_deref(), Some(CONTENT_ID));
+        }
+    }
+
+    #[test]
+    fn truncated_videos_have_no_content_id() {
+        let data = video_file(false);
+        let path = write_temp_file("truncated.mov", &data);
+
+        for len in (0..data.len()).rev() {
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_len(len as u64))
+                .unwrap();
+            assert!(quicktime_content_id(&path).is_none(), "length {len}");
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_box_sizes() {
+        let mut data = mp4_box(b"ftyp", b"qt  ");
+        data.extend(4u32.to_be_bytes());
+        data.extend(b"moov");
+        let path = write_temp_file("small-box.mov", &data);
+        assert!(read_moov(&mut File::open(&path).unwrap()).is_none());
+        fs::remove_file(&path).unwrap();
+
+        let mut data = mp4_box(b"ftyp", b"qt  ");
+        data.extend((MAX_MOOV_SIZE as u32 + 1).to_be_bytes());
+        data.extend(b"moov");
+        let path = write_temp_file("large-moov.mov", &data);
+        assert!(read_moov(&mut File::open(&path).unwrap()).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn box_iteration_stops_at_broken_boxes() {
+        let mut data = mp4_box(b"free", &[1, 2]);
+        data.extend(mp4_box(b"skip", &[]));
+        data.extend(u32::MAX.to_be_bytes());
+        data.extend(b"meta");
+
+        let types: Vec<_> = boxes(&data).map(|(box_type, _)| box_type).collect();
+        assert_eq!(types, [b"free", b"skip"]);
+        assert!(find_box(&data, b"meta").is_none());
+        assert!(
+            boxes(&[0, 0, 0, 0, b'f', b'r', b'e', b'e'])
+                .next()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn key_index_handles_malformed_keys() {
+        let mut keys = vec![0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+        keys.extend(((8 + CONTENT_ID_KEY.len()) as u32).to_be_bytes());
+        keys.extend(b"mdta");
+        keys.extend(CONTENT_ID_KEY);
+
+        assert_eq!(key_index(&keys, CONTENT_ID_KEY), Some(1));
+        assert!(key_index(&keys, b"com.apple.quicktime.make").is_none());
+        assert!(key_index(&keys[..keys.len() - 1], CONTENT_ID_KEY).is_none());
+        assert!(key_index(&keys[..6], CONTENT_ID_KEY).is_none());
+    }
+}
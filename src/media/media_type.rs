@@ -1,16 +1,42 @@
+use super::sniff::{self, HEADER_SIZE};
 use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
 #[derive(Clone, Copy)]
 pub enum ImageType {
     Jpeg,
     Heic,
+    Heif,
+    Avif,
+    Png,
+    WebP,
+    Gif,
+    Raw(RawType),
+}
+
+/// Camera RAW formats. They are shown by their embedded JPEG previews
+#[derive(Clone, Copy)]
+pub enum RawType {
+    Dng,
+    Cr2,
+    Cr3,
+    Nef,
+    Arw,
 }
 
 #[derive(Clone, Copy)]
 pub enum VideoType {
     Mp4,
     Mov,
+    M4v,
+    ThreeGp,
+    /// MPEG transport stream used by AVCHD camcorders
+    Mts,
+    Avi,
+    Mkv,
+    WebM,
 }
 
 #[derive(Clone, Copy)]
@@ -20,8 +46,24 @@ pub enum MediaType {
 }
 
 impl ImageType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "jpeg",
+            ImageType::Heic => "heic",
+            ImageType::Heif => "heif",
+            ImageType::Avif => "avif",
+            ImageType::Png => "png",
+            ImageType::WebP => "webp",
+            ImageType::Gif => "gif",
+            ImageType::Raw(raw_type) => raw_type.name(),
+        }
+    }
+
     pub fn from_ext(ext: &OsStr) -> Option<ImageType> {
-        if ext.eq_ignore_ascii_case("jpeg") || ext.eq_ignore_ascii_case("jpg") {
+        if ["jpeg", "jpg", "jpe"]
+            .iter()
+            .any(|jpeg_ext| ext.eq_ignore_ascii_case(jpeg_ext))
+        {
             return Some(ImageType::Jpeg);
         }
 
@@ -29,28 +71,112 @@ impl ImageType {
             return Some(ImageType::Heic);
         }
 
-        None
+        if ext.eq_ignore_ascii_case("heif") || ext.eq_ignore_ascii_case("hif") {
+            return Some(ImageType::Heif);
+        }
+
+        if ext.eq_ignore_ascii_case("avif") {
+            return Some(ImageType::Avif);
+        }
+
+        if ext.eq_ignore_ascii_case("png") {
+            return Some(ImageType::Png);
+        }
+
+        if ext.eq_ignore_ascii_case("webp") {
+            return Some(ImageType::WebP);
+        }
+
+        if ext.eq_ignore_ascii_case("gif") {
+            return Some(ImageType::Gif);
+        }
+
+        RawType::from_ext(ext).map(ImageType::Raw)
+    }
+}
+
+impl RawType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RawType::Dng => "dng",
+            RawType::Cr2 => "cr2",
+            RawType::Cr3 => "cr3",
+            RawType::Nef => "nef",
+            RawType::Arw => "arw",
+        }
+    }
+
+    pub fn from_ext(ext: &OsStr) -> Option<RawType> {
+        [
+            RawType::Dng,
+            RawType::Cr2,
+            RawType::Cr3,
+            RawType::Nef,
+            RawType::Arw,
+        ]
+        .into_iter()
+        .find(|raw_type| ext.eq_ignore_ascii_case(raw_type.name()))
     }
 }
 
 impl VideoType {
-    pub fn from_ext(ext: &OsStr) -> Option<VideoType> {
-        if ext.eq_ignore_ascii_case("mp4") {
-            return Some(VideoType::Mp4);
+    const ALL: [VideoType; 8] = [
+        VideoType::Mp4,
+        VideoType::Mov,
+        VideoType::M4v,
+        VideoType::ThreeGp,
+        VideoType::Mts,
+        VideoType::Avi,
+        VideoType::Mkv,
+        VideoType::WebM,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VideoType::Mp4 => "mp4",
+            VideoType::Mov => "mov",
+            VideoType::M4v => "m4v",
+            VideoType::ThreeGp => "3gp",
+            VideoType::Mts => "mts",
+            VideoType::Avi => "avi",
+            VideoType::Mkv => "mkv",
+            VideoType::WebM => "webm",
         }
+    }
 
-        if ext.eq_ignore_ascii_case("mov") {
-            return Some(VideoType::Mov);
+    pub fn from_ext(ext: &OsStr) -> Option<VideoType> {
+        if ext.eq_ignore_ascii_case("m2ts") {
+            return Some(VideoType::Mts);
         }
 
-        None
+        VideoType::ALL
+            .into_iter()
+            .find(|video_type| ext.eq_ignore_ascii_case(video_type.name()))
+    }
+
+    /// Containers that are used by camcorders recording interlaced video
+    pub fn may_be_interlaced(&self) -> bool {
+        matches!(self, VideoType::Mts | VideoType::Avi)
     }
 }
 
 impl MediaType {
-    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<MediaType> {
-        let ext = path.as_ref().extension()?;
+    /// Detects the media type by the file content. The extension is used only as a hint:
+    /// files with unknown extensions are not read, and files with unrecognized content
+    /// fall back to their extension type
+    pub fn detect<P: AsRef<Path>>(path: P) -> Option<MediaType> {
+        let path = path.as_ref();
+        let hint = match path.extension() {
+            Some(ext) => Some(MediaType::from_ext(ext)?),
+            None => None,
+        };
 
+        read_header(path)
+            .and_then(|header| sniff::sniff(&header))
+            .or(hint)
+    }
+
+    pub fn from_ext(ext: &OsStr) -> Option<MediaType> {
         if let Some(image_type) = ImageType::from_ext(ext) {
             return Some(MediaType::Image(image_type));
         }
@@ -61,4 +187,35 @@ impl MediaType {
 
         None
     }
+
+    /// Canonical media type name (used as a database representation)
+    pub fn name(&self) -> &'static str {
+        match self {
+            MediaType::Image(image_type) => image_type.name(),
+            MediaType::Video(video_type) => video_type.name(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MediaType> {
+        MediaType::from_ext(OsStr::new(name))
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self, MediaType::Video(_))
+    }
+
+    pub fn is_raw(&self) -> bool {
+        matches!(self, MediaType::Image(ImageType::Raw(_)))
+    }
+}
+
+fn read_header(path: &Path) -> Option<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    File::open(path)
+        .ok()?
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    Some(header)
 }
@@ -1,87 +1,315 @@
-use super::MediaType;
+use super::exif_chunk::ExifChunk;
+use super::xmp::XmpMetadata;
+use super::{ImageType, MediaType};
 use crate::exif_orientation::ExifOrientation;
 use anyhow::anyhow;
-use chrono::{DateTime, FixedOffset, Utc};
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeDelta, Utc};
+use gstreamer::glib::filename_to_uri;
+use gstreamer::{ClockTime, tags};
+use gstreamer_pbutils::Discoverer;
+use image::{ImageDecoder, ImageReader};
+use libheif_rs::HeifContext;
 use nom_exif::{
-    Exif, ExifIter, ExifTag, MediaKind, MediaParser, MediaSource, TrackInfo, TrackInfoTag,
+    EntryValue, Exif, ExifIter, ExifTag, MediaKind, MediaParser, MediaSource, TrackInfo,
+    TrackInfoTag,
 };
 use std::fs;
 use std::path::Path;
 
+const DISCOVERER_TIMEOUT_SEC: u64 = 10;
+
+/// Parsers that are created once per indexing worker and reused for its files
+pub struct MetadataParser {
+    media_parser: MediaParser,
+    discoverer: Discoverer,
+}
+
+impl MetadataParser {
+    /// Requires GStreamer to be initialized
+    pub fn new() -> anyhow::Result<MetadataParser> {
+        Ok(MetadataParser {
+            media_parser: MediaParser::new(),
+            discoverer: Discoverer::new(ClockTime::from_seconds(DISCOVERER_TIMEOUT_SEC))?,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct MediaMetadata {
+    pub datetime: DateTime<FixedOffset>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub details: MediaDetails,
+}
+
 #[derive(Clone, Copy)]
-pub enum MediaMetadata {
-    Image {
-        datetime: DateTime<FixedOffset>,
-        orientation: ExifOrientation,
-    },
-    Video {
-        datetime: DateTime<FixedOffset>,
-        duration_ms: u64,
-    },
+pub enum MediaDetails {
+    Image { orientation: ExifOrientation },
+    Video { duration_ms: u64 },
 }
 
 impl MediaMetadata {
+    /// The `clock_offset` is applied to the capture time written by the camera,
+    /// but not to the file system time that is used as a fallback
     pub fn parse<P: AsRef<Path>>(
         path: P,
         mt: &MediaType,
+        parser: &mut MetadataParser,
+        clock_offset: TimeDelta,
+    ) -> anyhow::Result<MediaMetadata> {
+        let mp = &mut parser.media_parser;
+        match mt {
+            MediaType::Image(ImageType::Jpeg | ImageType::Raw(_)) => {
+                Self::parse_exif(&path, mp, clock_offset)
+            }
+            // Not all HEIF brands are recognized by the EXIF parser
+            MediaType::Image(ImageType::Heic | ImageType::Heif | ImageType::Avif) => {
+                Self::parse_exif(&path, mp, clock_offset)
+                    .or_else(|_| Self::parse_heif_header(&path, clock_offset))
+            }
+            // These formats often have no EXIF data, so the decoder is used as a fallback
+            MediaType::Image(_) => Self::parse_exif(&path, mp, clock_offset)
+                .or_else(|_| Self::parse_image_header(&path, clock_offset)),
+            // Containers like MPEG-TS and AVI are not supported by the track parser
+            MediaType::Video(_) => Self::parse_track(&path, mp, clock_offset)
+                .or_else(|_| Self::parse_discovered(&path, &parser.discoverer, clock_offset)),
+        }
+    }
+
+    fn parse_exif<P: AsRef<Path>>(
+        path: P,
         mp: &mut MediaParser,
+        clock_offset: TimeDelta,
     ) -> anyhow::Result<MediaMetadata> {
         let ms = MediaSource::open(&path)?;
+        if ms.kind() != MediaKind::Image {
+            return Err(anyhow!("No metadata found"));
+        }
 
-        match mt {
-            MediaType::Image(_) if ms.kind() == MediaKind::Image => {
-                let iter: ExifIter = mp.parse_exif(ms)?;
-                let exif: Exif = iter.into();
-                return Ok(MediaMetadata::Image {
-                    datetime: exif
-                        .get(ExifTag::CreateDate)
-                        .and_then(|e| e.as_datetime())
-                        .and_then(|dt| dt.aware())
-                        .or_else(|| fs_datetime(&path))
-                        .unwrap_or_default(),
-                    orientation: exif
-                        .get(ExifTag::Orientation)
-                        .and_then(|e| e.as_u16())
-                        .and_then(|u| (u as i64).try_into().ok())
-                        .unwrap_or_default(),
-                });
-            }
+        let iter: ExifIter = mp.parse_exif(ms)?;
+        let exif: Exif = iter.into();
+        Ok(MediaMetadata {
+            datetime: exif
+                .get(ExifTag::CreateDate)
+                .and_then(|e| e.as_datetime())
+                .and_then(|dt| dt.aware())
+                .map(|dt| dt + clock_offset)
+                .or_else(|| fs_datetime(&path))
+                .unwrap_or_default(),
+            width: exif
+                .get(ExifTag::ExifImageWidth)
+                .or_else(|| exif.get(ExifTag::ImageWidth))
+                .and_then(entry_u32),
+            height: exif
+                .get(ExifTag::ExifImageHeight)
+                .or_else(|| exif.get(ExifTag::ImageHeight))
+                .and_then(entry_u32),
+            camera_make: exif.get(ExifTag::Make).and_then(entry_string),
+            camera_model: exif.get(ExifTag::Model).and_then(entry_string),
+            details: MediaDetails::Image {
+                orientation: exif
+                    .get(ExifTag::Orientation)
+                    .and_then(|e| e.as_u16())
+                    .and_then(|u| (u as i64).try_into().ok())
+                    .unwrap_or_default(),
+            },
+        })
+    }
 
-            MediaType::Video(_) if ms.kind() == MediaKind::Track => {
-                let info: TrackInfo = mp.parse_track(ms)?;
-                return Ok(MediaMetadata::Video {
-                    datetime: info
-                        .get(TrackInfoTag::CreateDate)
-                        .and_then(|e| e.as_datetime())
-                        .and_then(|dt| dt.aware())
-                        .or_else(|| fs_datetime(&path))
-                        .unwrap_or_default(),
-                    duration_ms: info
-                        .get(TrackInfoTag::DurationMs)
-                        .and_then(|e| e.as_u64())
-                        .unwrap_or_default(),
-                });
-            }
+    /// Reads the image size and the EXIF block with libheif
+    fn parse_heif_header<P: AsRef<Path>>(
+        path: P,
+        clock_offset: TimeDelta,
+    ) -> anyhow::Result<MediaMetadata> {
+        let path_str = path
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| anyhow!("Failed to convert path to str"))?;
 
-            _ => {}
+        let ctx = HeifContext::read_from_file(path_str)?;
+        let handle = ctx.primary_image_handle()?;
+        let exif = handle
+            .metadata_block_ids(b"Exif")
+            .into_iter()
+            .find_map(|id| handle.metadata(id).ok())
+            .and_then(|block| ExifChunk::parse_heif_block(&block))
+            .unwrap_or_default();
+
+        Ok(MediaMetadata {
+            datetime: exif
+                .datetime
+                .map(|dt| dt + clock_offset)
+                .or_else(|| fs_datetime(&path))
+                .unwrap_or_default(),
+            width: Some(handle.width()).filter(|w| *w > 0),
+            height: Some(handle.height()).filter(|h| *h > 0),
+            camera_make: exif.camera_make,
+            camera_model: exif.camera_model,
+            details: MediaDetails::Image {
+                orientation: exif.orientation.unwrap_or_default(),
+            },
+        })
+    }
+
+    /// Reads the image size, the EXIF chunk and the XMP packet with the image decoder
+    fn parse_image_header<P: AsRef<Path>>(
+        path: P,
+        clock_offset: TimeDelta,
+    ) -> anyhow::Result<MediaMetadata> {
+        let mut decoder = ImageReader::open(&path)?
+            .with_guessed_format()?
+            .into_decoder()?;
+
+        let (width, height) = decoder.dimensions();
+        let exif = decoder
+            .exif_metadata()?
+            .and_then(|chunk| ExifChunk::parse(&chunk))
+            .unwrap_or_default();
+        let xmp = decoder
+            .xmp_metadata()?
+            .map(|packet| XmpMetadata::parse(&packet))
+            .unwrap_or_default();
+
+        Ok(MediaMetadata {
+            datetime: exif
+                .datetime
+                .or(xmp.datetime)
+                .map(|dt| dt + clock_offset)
+                .or_else(|| fs_datetime(&path))
+                .unwrap_or_default(),
+            width: Some(width).filter(|w| *w > 0),
+            height: Some(height).filter(|h| *h > 0),
+            camera_make: exif.camera_make.or(xmp.camera_make),
+            camera_model: exif.camera_model.or(xmp.camera_model),
+            details: MediaDetails::Image {
+                orientation: exif.orientation.or(xmp.orientation).unwrap_or_default(),
+            },
+        })
+    }
+
+    fn parse_track<P: AsRef<Path>>(
+        path: P,
+        mp: &mut MediaParser,
+        clock_offset: TimeDelta,
+    ) -> anyhow::Result<MediaMetadata> {
+        let ms = MediaSource::open(&path)?;
+        if ms.kind() != MediaKind::Track {
+            return Err(anyhow!("No metadata found"));
         }
 
-        Err(anyhow!("No metadata found"))
+        let info: TrackInfo = mp.parse_track(ms)?;
+        Ok(MediaMetadata {
+            datetime: info
+                .get(TrackInfoTag::CreateDate)
+                .and_then(|e| e.as_datetime())
+                .and_then(|dt| dt.aware())
+                .map(|dt| dt + clock_offset)
+                .or_else(|| fs_datetime(&path))
+                .unwrap_or_default(),
+            width: info.get(TrackInfoTag::Width).and_then(entry_u32),
+            height: info.get(TrackInfoTag::Height).and_then(entry_u32),
+            camera_make: info.get(TrackInfoTag::Make).and_then(entry_string),
+            camera_model: info.get(TrackInfoTag::Model).and_then(entry_string),
+            details: MediaDetails::Video {
+                duration_ms: info
+                    .get(TrackInfoTag::DurationMs)
+                    .and_then(|e| e.as_u64())
+                    .unwrap_or_default(),
+            },
+        })
+    }
+
+    /// Reads the stream info and tags with the GStreamer discoverer
+    fn parse_discovered<P: AsRef<Path>>(
+        path: P,
+        discoverer: &Discoverer,
+        clock_offset: TimeDelta,
+    ) -> anyhow::Result<MediaMetadata> {
+        let uri = filename_to_uri(path.as_ref(), None)?;
+        let info = discoverer.discover_uri(&uri)?;
+
+        let tags = info.tags();
+        let video_stream = info.video_streams().into_iter().next();
+
+        Ok(MediaMetadata {
+            datetime: tags
+                .as_ref()
+                .and_then(|tags| tags.get::<tags::DateTime>())
+                .and_then(|dt| gst_datetime(&dt.get()))
+                .map(|dt| dt + clock_offset)
+                .or_else(|| fs_datetime(&path))
+                .unwrap_or_default(),
+            width: video_stream.as_ref().map(|v| v.width()).filter(|w| *w > 0),
+            height: video_stream.as_ref().map(|v| v.height()).filter(|h| *h > 0),
+            camera_make: tags
+                .as_ref()
+                .and_then(|tags| tags.get::<tags::DeviceManufacturer>())
+                .map(|tag| tag.get().trim().to_string())
+                .filter(|s| !s.is_empty()),
+            camera_model: tags
+                .as_ref()
+                .and_then(|tags| tags.get::<tags::DeviceModel>())
+                .map(|tag| tag.get().trim().to_string())
+                .filter(|s| !s.is_empty()),
+            details: MediaDetails::Video {
+                duration_ms: info.duration().map(|d| d.mseconds()).unwrap_or_default(),
+            },
+        })
     }
 
     pub fn exif_orientation(&self) -> Option<ExifOrientation> {
-        match self {
-            MediaMetadata::Image { orientation, .. } => Some(*orientation),
-            MediaMetadata::Video { .. } => None,
+        match self.details {
+            MediaDetails::Image { orientation } => Some(orientation),
+            MediaDetails::Video { .. } => None,
         }
     }
 
-    pub fn timestamp(&self) -> i64 {
-        match self {
-            MediaMetadata::Image { datetime, .. } => datetime.timestamp(),
-            MediaMetadata::Video { datetime, .. } => datetime.timestamp(),
+    pub fn duration_ms(&self) -> Option<u64> {
+        match self.details {
+            MediaDetails::Image { .. } => None,
+            MediaDetails::Video { duration_ms } => Some(duration_ms),
         }
     }
+
+    pub fn timestamp(&self) -> i64 {
+        self.datetime.timestamp()
+    }
+
+    /// Offset of the original (local) timestamp from UTC in seconds
+    pub fn utc_offset(&self) -> i32 {
+        self.datetime.offset().local_minus_utc()
+    }
+}
+
+fn entry_u32(entry: &EntryValue) -> Option<u32> {
+    entry
+        .as_u32()
+        .or_else(|| entry.as_u16().map(u32::from))
+        .filter(|value| *value > 0)
+}
+
+fn entry_string(entry: &EntryValue) -> Option<String> {
+    entry
+        .as_str()
+        .map(|s| s.trim_matches(char::from(0)).trim())
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+}
+
+/// GStreamer dates keep the local time and the time zone offset in hours
+fn gst_datetime(dt: &gstreamer::DateTime) -> Option<DateTime<FixedOffset>> {
+    let date = NaiveDate::from_ymd_opt(dt.year(), dt.month()? as u32, dt.day()? as u32)?;
+    let time = date.and_hms_opt(
+        dt.hour()? as u32,
+        dt.minute()? as u32,
+        dt.second().unwrap_or_default() as u32,
+    )?;
+
+    let offset_sec = dt.time_zone_offset().unwrap_or_default() * 3600.0;
+    let offset = FixedOffset::east_opt(offset_sec.round() as i32)?;
+    time.and_local_timezone(offset).single()
 }
 
 fn fs_datetime<P: AsRef<Path>>(path: P) -> Option<DateTime<FixedOffset>> {
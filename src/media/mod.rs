@@ -1,8 +1,21 @@
+mod exif_chunk;
+mod live_photo;
 mod media_type;
 mod metadata;
+mod motion_photo;
+mod raw_preview;
+mod sniff;
+mod tiff;
+#[cfg(test)]
+mod tiff_writer;
+mod xmp;
 
+pub use self::exif_chunk::exif_thumbnail;
+pub use self::live_photo::live_photo_content_id;
 pub use self::media_type::*;
 pub use self::metadata::*;
+pub use self::motion_photo::{EmbeddedVideo, find_embedded_video};
+pub use self::raw_preview::largest_jpeg_preview;
 use crate::video::Video;
 use slint::Image;
 
@@ -0,0 +1,295 @@
+use super::xmp::XmpMetadata;
+use image::ImageDecoder;
+use image::codecs::jpeg::JpegDecoder;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Samsung puts the video after this marker and adds its own trailer to the end of the file
+const SAMSUNG_VIDEO_MARKER: &[u8] = b"MotionPhoto_Data";
+const SAMSUNG_TRAILER_END: &[u8] = b"SEFT";
+
+/// Location of the MP4 video embedded into a motion photo file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmbeddedVideo {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Finds the video of a Google or Samsung motion photo (a JPEG file with an appended MP4 video)
+pub fn find_embedded_video(path: &Path) -> Option<EmbeddedVideo> {
+    let file_len = fs::metadata(path).ok()?.len();
+    let trailer_len = samsung_trailer_len(path);
+
+    // The video described by XMP ends before the Samsung trailer
+    let xmp_video = xmp_video_length(path).and_then(|length| {
+        let video_end = file_len.checked_sub(trailer_len.unwrap_or_default())?;
+        Some(EmbeddedVideo {
+            offset: video_end.checked_sub(length)?,
+            length,
+        })
+    });
+
+    let is_mp4 = |video: &EmbeddedVideo| is_mp4_at(path, video.offset);
+    xmp_video
+        .filter(is_mp4)
+        .or_else(|| samsung_video(path, trailer_len?).filter(is_mp4))
+}
+
+/// The video described by XMP is located at the end of the file
+fn xmp_video_length(path: &Path) -> Option<u64> {
+    let mut decoder = JpegDecoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+    let packet = decoder.xmp_metadata().ok()??;
+    XmpMetadata::parse(&packet).motion_video_length
+}
+
+/// Samsung trailer ends with its little-endian size and the `SEFT` marker
+fn samsung_trailer_len(path: &Path) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    let mut trailer_end = [0; 8];
+    file.seek(SeekFrom::End(-(trailer_end.len() as i64))).ok()?;
+    file.read_exact(&mut trailer_end).ok()?;
+
+    if &trailer_end[4..] != SAMSUNG_TRAILER_END {
+        return None;
+    }
+
+    let size = u32::from_le_bytes(trailer_end[..4].try_into().ok()?);
+    Some(u64::from(size) + trailer_end.len() as u64)
+}
+
+/// Samsung files without XMP are recognized by the trailer, the video length is found
+/// by walking the top-level MP4 boxes
+fn samsung_video(path: &Path, trailer_len: u64) -> Option<EmbeddedVideo> {
+    let data = fs::read(path).ok()?;
+    let data = &data[..data.len().checked_sub(usize::try_from(trailer_len).ok()?)?];
+    let marker = data
+        .windows(SAMSUNG_VIDEO_MARKER.len())
+        .rposition(|w| w == SAMSUNG_VIDEO_MARKER)?;
+
+    let offset = marker + SAMSUNG_VIDEO_MARKER.len();
+    let length = mp4_len(&data[offset..]);
+
+    (length > 0).then_some(EmbeddedVideo {
+        offset: offset as u64,
+        length: length as u64,
+    })
+}
+
+fn is_mp4_at(path: &Path, offset: u64) -> bool {
+    read_box_header(path, offset).is_ok_and(|header| &header[4..] == b"ftyp")
+}
+
+fn read_box_header(path: &Path, offset: u64) -> io::Result<[u8; 8]> {
+    let mut header = [0; 8];
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+    Ok(header)
+}
+
+/// Sums the sizes of the top-level boxes until the data that doesn't look like a box
+fn mp4_len(data: &[u8]) -> usize {
+    let mut pos = 0;
+
+    while let Some(header) = data.get(pos..pos + 8) {
+        let is_box_type = header[4..]
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || *b == b' ');
+        if !is_box_type {
+            break;
+        }
+
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // The box extends to the end of the data
+            0 => (8, data.len() - pos),
+            // 64-bit size follows the box type
+            1 => match data.get(pos + 8..pos + 16) {
+                Some(large_size) => {
+                    let large_size = u64::from_be_bytes(large_size.try_into().unwrap());
+                    (16, usize::try_from(large_size).unwrap_or(usize::MAX))
+                }
+                None => break,
+            },
+            size => (8, size as usize),
+        };
+
+        if size < header_len || size > data.len() - pos {
+            break;
+        }
+
+        pos += size;
+    }
+
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ExtendedColorType, ImageEncoder};
+    use std::path::PathBuf;
+
+    fn mp4_box(box_type: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((8 + payload.len()) as u32).to_be_bytes().to_vec();
+        data.extend(box_type);
+        data.extend(payload);
+        data
+    }
+
+    fn mp4() -> Vec<u8> {
+        let mut data = mp4_box(b"ftyp", b"mp42\0\0\0\0isom");
+        data.extend(mp4_box(b"moov", &[0; 32]));
+        data.extend(mp4_box(b"mdat", &[0; 64]));
+        data
+    }
+
+    /// A JPEG image with the XMP packet in the APP1 segment
+    fn jpeg(xmp: Option<&str>) -> Vec<u8> {
+        let mut image = Vec::new();
+        JpegEncoder::new(&mut image)
+            .write_image(&[128; 64], 8, 8, ExtendedColorType::L8)
+            .unwrap();
+
+        let Some(xmp) = xmp else {
+            return image;
+        };
+
+        let mut payload = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+        payload.extend(xmp.as_bytes());
+
+        let mut data = image[..2].to_vec();
+        data.extend([0xFF, 0xE1]);
+        data.extend(((payload.len() + 2) as u16).to_be_bytes());
+        data.extend(payload);
+        data.extend(&image[2..]);
+        data
+    }
+
+    fn samsung_trailer() -> Vec<u8> {
+        let mut trailer = b"SEFH\0\0\0\0MotionPhoto_Data entry".to_vec();
+        trailer.extend((trailer.len() as u32).to_le_bytes());
+        trailer.extend(SAMSUNG_TRAILER_END);
+        trailer
+    }
+
+    fn find_in_temp_file(name: &str, data: &[u8]) -> Option<EmbeddedVideo> {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("photoflow-{}-{name}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        let video = find_embedded_video(&path);
+        fs::remove_file(&path).unwrap();
+        video
+    }
+
+    #[test]
+    fn finds_xmp_described_video() {
+        let video = mp4();
+        let xmp = format!(
+            r#"<rdf:Description GCamera:MicroVideoOffset="{}"/>"#,
+            video.len()
+        );
+        let mut data = jpeg(Some(&xmp));
+        let offset = data.len() as u64;
+        data.extend(&video);
+
+        let expected = EmbeddedVideo {
+            offset,
+            length: video.len() as u64,
+        };
+        assert_eq!(find_in_temp_file("google.jpg", &data), Some(expected));
+
+        // Samsung puts the trailer after the video described by XMP
+        data.extend(samsung_trailer());
+        assert_eq!(find_in_temp_file("samsung-xmp.jpg", &data), Some(expected));
+    }
+
+    #[test]
+    fn falls_back_to_samsung_marker() {
+        let video = mp4();
+        let xmp = format!(
+            r#"<rdf:Description GCamera:MicroVideoOffset="{}"/>"#,
+            video.len() + 5
+        );
+        let mut data = jpeg(Some(&xmp));
+        data.extend(SAMSUNG_VIDEO_MARKER);
+        let offset = data.len() as u64;
+        data.extend(&video);
+        data.extend(samsung_trailer());
+
+        let expected = EmbeddedVideo {
+            offset,
+            length: video.len() as u64,
+        };
+        assert_eq!(
+            find_in_temp_file("samsung-wrong-xmp.jpg", &data),
+            Some(expected)
+        );
+
+        let mut data = jpeg(None);
+        data.extend(SAMSUNG_VIDEO_MARKER);
+        let offset = data.len() as u64;
+        data.extend(&video);
+        data.extend(samsung_trailer());
+
+        let expected = EmbeddedVideo {
+            offset,
+            length: video.len() as u64,
+        };
+        assert_eq!(find_in_temp_file("samsung.jpg", &data), Some(expected));
+    }
+
+    #[test]
+    fn ignores_files_without_video() {
+        let mut data = jpeg(Some(r#"<rdf:Description GCamera:MicroVideoOffset="16"/>"#));
+        data.extend([0; 16]);
+        assert!(find_in_temp_file("no-video.jpg", &data).is_none());
+
+        let mut data = jpeg(None);
+        data.extend(SAMSUNG_VIDEO_MARKER);
+        data.extend(b"not a video");
+        data.extend(samsung_trailer());
+        assert!(find_in_temp_file("broken-samsung.jpg", &data).is_none());
+
+        assert!(find_in_temp_file("trailer-only.jpg", &samsung_trailer()).is_none());
+    }
+
+    #[test]
+    fn sums_top_level_boxes() {
+        let video = mp4();
+        let mut data = video.clone();
+        data.extend(b"SEFH trailing data");
+        assert_eq!(mp4_len(&data), video.len());
+
+        assert_eq!(mp4_len(&[]), 0);
+        assert_eq!(mp4_len(&video[..video.len() - 1]), video.len() - 72);
+        assert_eq!(mp4_len(b"\0\0\0\x04ftyp"), 0);
+    }
+
+    #[test]
+    fn reads_large_and_open_ended_boxes() {
+        let mut data = mp4_box(b"ftyp", b"mp42");
+        data.extend(1u32.to_be_bytes());
+        data.extend(b"mdat");
+        data.extend(24u64.to_be_bytes());
+        data.extend([0; 8]);
+        assert_eq!(mp4_len(&data), data.len());
+
+        // Truncated or too small 64-bit sizes
+        assert_eq!(mp4_len(&data[..data.len() - 1]), 12);
+        assert_eq!(mp4_len(&data[..20]), 12);
+        let mut small = data.clone();
+        small[20..28].copy_from_slice(&8u64.to_be_bytes());
+        assert_eq!(mp4_len(&small), 12);
+        let mut huge = data.clone();
+        huge[20..28].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(mp4_len(&huge), 12);
+
+        let mut data = mp4_box(b"ftyp", b"mp42");
+        data.extend(0u32.to_be_bytes());
+        data.extend(b"mdat");
+        data.extend([0; 100]);
+        assert_eq!(mp4_len(&data), data.len());
+    }
+}
@@ -0,0 +1,268 @@
+use super::tiff::{Ifd, Tiff};
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+const COMPRESSION_OLD_JPEG: u32 = 6;
+const COMPRESSION_JPEG: u32 = 7;
+
+/// Limits the IFD walking of broken files that have reference loops
+const MAX_IFD_COUNT: usize = 64;
+
+/// Finds the largest embedded JPEG preview of a RAW file.
+/// TIFF-based files are walked by their IFDs, other ones (like CR3) are scanned for JPEG streams
+pub fn largest_jpeg_preview(data: &[u8]) -> Option<&[u8]> {
+    let mut previews = Tiff::new(data).map(tiff_previews).unwrap_or_default();
+    if previews.is_empty() {
+        previews = scan_jpeg_streams(data);
+    }
+
+    previews.into_iter().max_by_key(|preview| preview.len())
+}
+
+fn tiff_previews<'a>(tiff: Tiff<'a>) -> Vec<&'a [u8]> {
+    let mut previews = Vec::new();
+    let mut pending = Vec::from_iter(tiff.first_ifd());
+    let mut visited = 0;
+
+    while let Some(ifd) = pending.pop() {
+        visited += 1;
+        if visited > MAX_IFD_COUNT {
+            break;
+        }
+
+        previews.extend(ifd_preview(tiff, &ifd));
+        pending.extend(ifd.next());
+        pending.extend(ifd.sub_ifds(TAG_SUB_IFDS));
+    }
+
+    previews
+}
+
+/// Previews are referenced either as a JPEG interchange format or as a JPEG-compressed strip
+fn ifd_preview<'a>(tiff: Tiff<'a>, ifd: &Ifd<'a>) -> Option<&'a [u8]> {
+    let jpeg_offset = ifd.entry(TAG_JPEG_OFFSET).and_then(|e| e.as_u32());
+    let jpeg_length = ifd.entry(TAG_JPEG_LENGTH).and_then(|e| e.as_u32());
+
+    let (offset, length) = match (jpeg_offset, jpeg_length) {
+        (Some(offset), Some(length)) => (offset, length),
+        _ => {
+            let compression = ifd.entry(TAG_COMPRESSION)?.as_u32()?;
+            if compression != COMPRESSION_OLD_JPEG && compression != COMPRESSION_JPEG {
+                return None;
+            }
+
+            let offset = ifd.entry(TAG_STRIP_OFFSETS)?.as_u32()?;
+            let length = ifd.entry(TAG_STRIP_BYTE_COUNTS)?.as_u32()?;
+            (offset, length)
+        }
+    };
+
+    tiff.bytes(offset, length)
+        .filter(|jpeg| jpeg_stream_len(jpeg).is_some())
+}
+
+fn scan_jpeg_streams(data: &[u8]) -> Vec<&[u8]> {
+    const SOI: [u8; 3] = [0xFF, 0xD8, 0xFF];
+
+    let mut streams = Vec::new();
+    let mut pos = 0;
+
+    while let Some(idx) = data[pos..].windows(SOI.len()).position(|w| w == SOI) {
+        let start = pos + idx;
+        match jpeg_stream_len(&data[start..]) {
+            Some(len) => {
+                streams.push(&data[start..start + len]);
+                pos = start + len;
+            }
+            None => pos = start + SOI.len(),
+        }
+    }
+
+    streams
+}
+
+/// Walks the JPEG markers to find the stream end. Returns `None` for streams that can't be decoded
+/// as a preview, for example the lossless JPEG used to store the raw sensor data
+fn jpeg_stream_len(data: &[u8]) -> Option<usize> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut pos = 2;
+    let mut has_image_frame = false;
+
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+
+        let marker = *data.get(pos + 1)?;
+        match marker {
+            // Fill bytes
+            0xFF => pos += 1,
+            // End of image
+            0xD9 => return has_image_frame.then_some(pos + 2),
+            // Markers without a payload
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            _ => {
+                let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+                match marker {
+                    // Baseline, extended sequential and progressive frames
+                    0xC0..=0xC2 => has_image_frame = true,
+                    // Other frame types
+                    0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+                    _ => {}
+                }
+
+                pos += 2 + len;
+                if marker == 0xDA {
+                    pos = entropy_data_end(data, pos)?;
+                }
+            }
+        }
+    }
+}
+
+/// Finds the next marker after the entropy-coded data
+fn entropy_data_end(data: &[u8], start: usize) -> Option<usize> {
+    let mut pos = start;
+    loop {
+        pos += data.get(pos..)?.iter().position(|b| *b == 0xFF)?;
+        match *data.get(pos + 1)? {
+            // Stuffed zero and restart markers are a part of the entropy-coded data
+            0x00 | 0xD0..=0xD7 => pos += 2,
+            _ => return Some(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::tiff_writer::TiffWriter;
+
+    /// A marker-level JPEG stream with the frame of the given type and the entropy-coded data
+    fn jpeg(frame_marker: u8, entropy_data: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        data.extend([0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00]);
+        data.extend([
+            0xFF,
+            frame_marker,
+            0x00,
+            0x0B,
+            8,
+            0,
+            16,
+            0,
+            16,
+            1,
+            1,
+            0x11,
+            0,
+        ]);
+        data.extend([0xFF, 0xDA, 0x00, 0x08, 1, 1, 0, 0, 0x3F, 0]);
+        data.extend(entropy_data);
+        data.extend([0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn finds_jpeg_stream_end() {
+        let stream = jpeg(0xC0, &[1, 2, 0xFF, 0x00, 3, 0xFF, 0xD0, 4]);
+        let mut data = stream.clone();
+        data.extend(b"trailing data");
+
+        assert_eq!(jpeg_stream_len(&data), Some(stream.len()));
+        assert_eq!(
+            jpeg_stream_len(&jpeg(0xC2, &[1])),
+            Some(jpeg(0xC2, &[1]).len())
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_and_truncated_streams() {
+        assert!(jpeg_stream_len(&jpeg(0xC3, &[1, 2])).is_none());
+        assert!(jpeg_stream_len(&[0xFF, 0xD8, 0xFF, 0xD9]).is_none());
+        assert!(jpeg_stream_len(&[0xFF, 0xD8, 0x00, 0xD9]).is_none());
+        assert!(jpeg_stream_len(b"not a jpeg").is_none());
+
+        let stream = jpeg(0xC0, &[1, 2, 3]);
+        for len in 0..stream.len() {
+            assert!(jpeg_stream_len(&stream[..len]).is_none());
+        }
+    }
+
+    #[test]
+    fn picks_largest_tiff_preview() {
+        let small = jpeg(0xC0, &[1]);
+        let large = jpeg(0xC0, &[1; 32]);
+        let lossless = jpeg(0xC3, &[1; 64]);
+
+        let mut w = TiffWriter::new(false);
+        let small_offset = w.blob(&small);
+        let large_offset = w.blob(&large);
+        let lossless_offset = w.blob(&lossless);
+
+        let entries = vec![
+            w.short(TAG_COMPRESSION, &[7]),
+            w.long(TAG_STRIP_OFFSETS, &[lossless_offset]),
+            w.long(TAG_STRIP_BYTE_COUNTS, &[lossless.len() as u32]),
+        ];
+        let raw_ifd = w.ifd(entries, 0);
+        let entries = vec![
+            w.long(TAG_JPEG_OFFSET, &[large_offset]),
+            w.long(TAG_JPEG_LENGTH, &[large.len() as u32]),
+        ];
+        let ifd1 = w.ifd(entries, 0);
+        let entries = vec![
+            w.short(TAG_COMPRESSION, &[6]),
+            w.long(TAG_STRIP_OFFSETS, &[small_offset]),
+            w.long(TAG_STRIP_BYTE_COUNTS, &[small.len() as u32]),
+            w.long(TAG_SUB_IFDS, &[raw_ifd]),
+        ];
+        let ifd0 = w.ifd(entries, ifd1);
+        let data = w.finish(ifd0);
+
+        assert_eq!(largest_jpeg_preview(&data), Some(&large[..]));
+        for len in 0..data.len() {
+            largest_jpeg_preview(&data[..len]);
+        }
+    }
+
+    #[test]
+    fn ignores_broken_tiff_references() {
+        let mut w = TiffWriter::new(true);
+        let entries = vec![
+            w.short(TAG_COMPRESSION, &[1]),
+            w.long(TAG_JPEG_OFFSET, &[8]),
+            w.long(TAG_JPEG_LENGTH, &[u32::MAX]),
+        ];
+        // The IFD references itself as the next one
+        let ifd0 = w.ifd(entries, 8);
+        let data = w.finish(ifd0);
+
+        assert!(largest_jpeg_preview(&data).is_none());
+    }
+
+    #[test]
+    fn scans_non_tiff_files() {
+        let small = jpeg(0xC0, &[1]);
+        let large = jpeg(0xC1, &[1; 32]);
+
+        let mut data = b"\0\0\0\x18ftypcrx ".to_vec();
+        data.extend([0xFF, 0xD8, 0xFF, 0x00]);
+        data.extend(&small);
+        data.extend(b"padding");
+        data.extend(&large);
+        data.extend(&jpeg(0xC3, &[1; 64]));
+        data.extend(&large[..large.len() - 2]);
+
+        assert_eq!(largest_jpeg_preview(&data), Some(&large[..]));
+        assert!(largest_jpeg_preview(b"no previews").is_none());
+    }
+}
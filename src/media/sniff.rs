@@ -0,0 +1,194 @@
+use super::{ImageType, MediaType, RawType, VideoType};
+use std::iter;
+
+/// Enough to read the `ftyp` box with several compatible brands
+/// and to check the sync bytes of two MPEG-TS packets
+pub const HEADER_SIZE: usize = 256;
+
+const TS_PACKET_SIZE: usize = 188;
+const M2TS_PACKET_SIZE: usize = 192;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+/// Detects the media type by the magic bytes of the file header
+pub fn sniff(header: &[u8]) -> Option<MediaType> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some(MediaType::Image(ImageType::Jpeg));
+    }
+
+    if header.starts_with(b"\x89PNG\r\n\x1A\n") {
+        return Some(MediaType::Image(ImageType::Png));
+    }
+
+    if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        return Some(MediaType::Image(ImageType::Gif));
+    }
+
+    if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+        return Some(MediaType::Image(ImageType::WebP));
+    }
+
+    if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"AVI ") {
+        return Some(MediaType::Video(VideoType::Avi));
+    }
+
+    // The EBML header contains the document type
+    if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        let is_webm = header.windows(4).any(|w| w == b"webm");
+        let video_type = if is_webm {
+            VideoType::WebM
+        } else {
+            VideoType::Mkv
+        };
+        return Some(MediaType::Video(video_type));
+    }
+
+    // Plain transport stream packets or M2TS packets with 4-byte timecode prefixes
+    if is_ts_sync(header, 0, TS_PACKET_SIZE) || is_ts_sync(header, 4, M2TS_PACKET_SIZE) {
+        return Some(MediaType::Video(VideoType::Mts));
+    }
+
+    // Other TIFF-based RAW formats are only distinguished by the extension
+    if header.starts_with(b"II*\0") && header.get(8..10) == Some(b"CR") {
+        return Some(MediaType::Image(ImageType::Raw(RawType::Cr2)));
+    }
+
+    if header.get(4..8) == Some(b"ftyp") {
+        return sniff_ftyp(header);
+    }
+
+    None
+}
+
+fn is_ts_sync(header: &[u8], offset: usize, packet_size: usize) -> bool {
+    header.get(offset) == Some(&TS_SYNC_BYTE)
+        && header.get(offset + packet_size) == Some(&TS_SYNC_BYTE)
+}
+
+/// ISO base media files are recognized by the major brand or the first known compatible brand
+fn sniff_ftyp(header: &[u8]) -> Option<MediaType> {
+    let box_size = u32::from_be_bytes(header.get(..4)?.try_into().ok()?) as usize;
+    let major_brand = header.get(8..12)?;
+    let compatible_brands = header
+        .get(16..box_size.min(header.len()))
+        .unwrap_or_default()
+        .chunks_exact(4);
+
+    let mut brands = iter::once(major_brand).chain(compatible_brands);
+    if let Some(media_type) = brands.clone().find_map(brand_media_type) {
+        return Some(media_type);
+    }
+
+    // Generic HEIF brands without a known codec brand
+    brands
+        .any(|brand| brand == b"mif1" || brand == b"msf1")
+        .then_some(MediaType::Image(ImageType::Heif))
+}
+
+fn brand_media_type(brand: &[u8]) -> Option<MediaType> {
+    match brand {
+        b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" => {
+            Some(MediaType::Image(ImageType::Heic))
+        }
+        b"avif" | b"avis" => Some(MediaType::Image(ImageType::Avif)),
+        b"crx " => Some(MediaType::Image(ImageType::Raw(RawType::Cr3))),
+        b"qt  " => Some(MediaType::Video(VideoType::Mov)),
+        b"M4V " | b"M4VH" | b"M4VP" => Some(MediaType::Video(VideoType::M4v)),
+        b"3gp4" | b"3gp5" | b"3gp6" | b"3gp7" | b"3g2a" | b"3g2b" | b"3g2c" => {
+            Some(MediaType::Video(VideoType::ThreeGp))
+        }
+        b"isom" | b"iso2" | b"iso4" | b"iso5" | b"iso6" | b"mp41" | b"mp42" | b"avc1" | b"dash" => {
+            Some(MediaType::Video(VideoType::Mp4))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniff_name(header: &[u8]) -> Option<&'static str> {
+        sniff(header).map(|media_type| media_type.name())
+    }
+
+    fn ftyp(major_brand: &[u8; 4], compatible_brands: &[&[u8; 4]]) -> Vec<u8> {
+        let size = 16 + compatible_brands.len() * 4;
+        let mut header = (size as u32).to_be_bytes().to_vec();
+        header.extend(b"ftyp");
+        header.extend(major_brand);
+        header.extend([0; 4]);
+        compatible_brands
+            .iter()
+            .for_each(|brand| header.extend(*brand));
+        header.extend(b"\0\0\0\x08free");
+        header
+    }
+
+    #[test]
+    fn detects_image_signatures() {
+        assert_eq!(sniff_name(b"\xFF\xD8\xFF\xE1"), Some("jpeg"));
+        assert_eq!(sniff_name(b"\x89PNG\r\n\x1A\n\0\0"), Some("png"));
+        assert_eq!(sniff_name(b"GIF89a"), Some("gif"));
+        assert_eq!(sniff_name(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(sniff_name(b"II*\0\x10\0\0\0CR\x02\0"), Some("cr2"));
+    }
+
+    #[test]
+    fn detects_video_signatures() {
+        assert_eq!(sniff_name(b"RIFF\0\0\0\0AVI LIST"), Some("avi"));
+        assert_eq!(
+            sniff_name(b"\x1A\x45\xDF\xA3\x42\x82\x84webm"),
+            Some("webm")
+        );
+        assert_eq!(
+            sniff_name(b"\x1A\x45\xDF\xA3\x42\x82\x88matroska"),
+            Some("mkv")
+        );
+
+        let mut ts = vec![0; 2 * TS_PACKET_SIZE];
+        ts[0] = TS_SYNC_BYTE;
+        ts[TS_PACKET_SIZE] = TS_SYNC_BYTE;
+        assert_eq!(sniff_name(&ts), Some("mts"));
+
+        let mut m2ts = vec![0; 2 * M2TS_PACKET_SIZE];
+        m2ts[4] = TS_SYNC_BYTE;
+        m2ts[4 + M2TS_PACKET_SIZE] = TS_SYNC_BYTE;
+        assert_eq!(sniff_name(&m2ts), Some("mts"));
+    }
+
+    #[test]
+    fn detects_ftyp_brands() {
+        assert_eq!(sniff_name(&ftyp(b"heic", &[b"mif1"])), Some("heic"));
+        assert_eq!(
+            sniff_name(&ftyp(b"mif1", &[b"miaf", b"avif"])),
+            Some("avif")
+        );
+        assert_eq!(sniff_name(&ftyp(b"mif1", &[b"miaf"])), Some("heif"));
+        assert_eq!(sniff_name(&ftyp(b"crx ", &[b"isom"])), Some("cr3"));
+        assert_eq!(sniff_name(&ftyp(b"qt  ", &[])), Some("mov"));
+        assert_eq!(sniff_name(&ftyp(b"M4V ", &[b"isom"])), Some("m4v"));
+        assert_eq!(sniff_name(&ftyp(b"3gp5", &[])), Some("3gp"));
+        assert_eq!(sniff_name(&ftyp(b"XAVC", &[b"iso6", b"mp42"])), Some("mp4"));
+        assert_eq!(sniff_name(&ftyp(b"XAVC", &[b"XAVC"])), None);
+    }
+
+    #[test]
+    fn rejects_truncated_and_malformed_headers() {
+        assert_eq!(sniff_name(b""), None);
+        assert_eq!(sniff_name(b"\xFF\xD8"), None);
+        assert_eq!(sniff_name(b"RIFF\0\0\0\0WEB"), None);
+        assert_eq!(sniff_name(b"II*\0\x10\0\0\0C"), None);
+        assert_eq!(sniff_name(&[TS_SYNC_BYTE; 100]), None);
+        assert_eq!(sniff_name(b"\0\0\0\x10ftyphe"), None);
+
+        // Compatible brands beyond the box size or the header are ignored
+        let mut header = ftyp(b"XAVC", &[b"heic"]);
+        header[..4].copy_from_slice(&16u32.to_be_bytes());
+        assert_eq!(sniff_name(&header), None);
+        let header = ftyp(b"XAVC", &[b"mif1"]);
+        assert_eq!(sniff_name(&header[..18]), None);
+        let mut header = ftyp(b"XAVC", &[b"avif"]);
+        header[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(sniff_name(&header), Some("avif"));
+    }
+}
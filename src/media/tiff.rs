@@ -0,0 +1,307 @@
+/// Minimal reader of the TIFF structure used by raw EXIF chunks
+#[derive(Clone, Copy)]
+pub struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct Ifd<'a> {
+    tiff: Tiff<'a>,
+    offset: usize,
+    count: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct IfdEntry<'a> {
+    tiff: Tiff<'a>,
+    kind: u16,
+    count: usize,
+    value_offset: usize,
+}
+
+const ENTRY_SIZE: usize = 12;
+
+impl<'a> Tiff<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Tiff<'a>> {
+        let big_endian = match data.get(..4)? {
+            [b'I', b'I', 42, 0] => false,
+            [b'M', b'M', 0, 42] => true,
+            _ => return None,
+        };
+
+        Some(Tiff { data, big_endian })
+    }
+
+    /// A structure without the TIFF header (like a maker note) that has the offsets relative to `data`
+    pub fn headless(data: &'a [u8], big_endian: bool) -> Tiff<'a> {
+        Tiff { data, big_endian }
+    }
+
+    pub fn first_ifd(self) -> Option<Ifd<'a>> {
+        self.ifd_at(self.u32_at(4)? as usize)
+    }
+
+    pub fn bytes(self, offset: u32, length: u32) -> Option<&'a [u8]> {
+        let start = offset as usize;
+        self.data.get(start..start.checked_add(length as usize)?)
+    }
+
+    pub fn ifd_at(self, offset: usize) -> Option<Ifd<'a>> {
+        if offset == 0 {
+            return None;
+        }
+
+        let count = self.u16_at(offset)? as usize;
+        let entries_end = offset + 2 + count * ENTRY_SIZE;
+        if entries_end > self.data.len() {
+            return None;
+        }
+
+        Some(Ifd {
+            tiff: self,
+            offset,
+            count,
+        })
+    }
+
+    fn u16_at(self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32_at(self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+}
+
+impl<'a> Ifd<'a> {
+    pub fn entry(&self, tag: u16) -> Option<IfdEntry<'a>> {
+        (0..self.count)
+            .map(|idx| self.offset + 2 + idx * ENTRY_SIZE)
+            .find(|pos| self.tiff.u16_at(*pos) == Some(tag))
+            .and_then(|pos| self.entry_at(pos))
+    }
+
+    fn entry_at(&self, pos: usize) -> Option<IfdEntry<'a>> {
+        let kind = self.tiff.u16_at(pos + 2)?;
+        let count = self.tiff.u32_at(pos + 4)? as usize;
+        let size = type_size(kind)?.checked_mul(count)?;
+
+        let value_offset = if size <= 4 {
+            pos + 8
+        } else {
+            self.tiff.u32_at(pos + 8)? as usize
+        };
+
+        if value_offset.checked_add(size)? > self.tiff.data.len() {
+            return None;
+        }
+
+        Some(IfdEntry {
+            tiff: self.tiff,
+            kind,
+            count,
+            value_offset,
+        })
+    }
+
+    /// The IFD referenced by an offset tag like `ExifIFDPointer`
+    pub fn sub_ifd(&self, tag: u16) -> Option<Ifd<'a>> {
+        let offset = self.entry(tag)?.as_u32()?;
+        self.tiff.ifd_at(offset as usize)
+    }
+
+    /// IFDs referenced by an offset array tag like `SubIFDs`
+    pub fn sub_ifds(&self, tag: u16) -> Vec<Ifd<'a>> {
+        let Some(entry) = self.entry(tag) else {
+            return Vec::new();
+        };
+
+        entry
+            .u32_values()
+            .filter_map(|offset| self.tiff.ifd_at(offset as usize))
+            .collect()
+    }
+
+    pub fn next(&self) -> Option<Ifd<'a>> {
+        let offset = self
+            .tiff
+            .u32_at(self.offset + 2 + self.count * ENTRY_SIZE)?;
+        self.tiff.ifd_at(offset as usize)
+    }
+}
+
+impl<'a> IfdEntry<'a> {
+    /// Reads the first value of a `SHORT` or `LONG` entry
+    pub fn as_u32(&self) -> Option<u32> {
+        self.u32_values().next()
+    }
+
+    /// Reads all values of a `SHORT` or `LONG` entry
+    pub fn u32_values(&self) -> impl Iterator<Item = u32> + 'a {
+        let entry = *self;
+        (0..self.count).filter_map(move |idx| match entry.kind {
+            3 => entry
+                .tiff
+                .u16_at(entry.value_offset + idx * 2)
+                .map(u32::from),
+            4 | 13 => entry.tiff.u32_at(entry.value_offset + idx * 4),
+            _ => None,
+        })
+    }
+
+    /// Raw value bytes of an entry of any type (like an `UNDEFINED` maker note)
+    pub fn as_bytes(&self) -> &'a [u8] {
+        let size = type_size(self.kind).unwrap_or_default() * self.count;
+        &self.tiff.data[self.value_offset..self.value_offset + size]
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        if self.kind != 2 {
+            return None;
+        }
+
+        std::str::from_utf8(self.as_bytes())
+            .ok()
+            .map(|s| s.trim_matches(char::from(0)).trim())
+            .filter(|s| !s.is_empty())
+    }
+}
+
+fn type_size(kind: u16) -> Option<usize> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::tiff_writer::TiffWriter;
+
+    #[test]
+    fn rejects_invalid_header() {
+        assert!(Tiff::new(b"").is_none());
+        assert!(Tiff::new(b"II\x2a").is_none());
+        assert!(Tiff::new(b"IM\x2a\0\x08\0\0\0").is_none());
+        assert!(Tiff::new(b"MM\x2a\0\0\0\0\x08").is_none());
+    }
+
+    #[test]
+    fn reads_values_of_both_byte_orders() {
+        for big_endian in [false, true] {
+            let mut w = TiffWriter::new(big_endian);
+            let entries = vec![
+                w.short(0x0100, &[640]),
+                w.long(0x0101, &[480]),
+                w.ascii(0x010F, "Camera\0"),
+                w.short(0x014A, &[1, 2, 3]),
+            ];
+            let ifd0 = w.ifd(entries, 0);
+            let data = w.finish(ifd0);
+            let ifd = Tiff::new(&data).unwrap().first_ifd().unwrap();
+
+            assert_eq!(ifd.entry(0x0100).unwrap().as_u32(), Some(640));
+            assert_eq!(ifd.entry(0x0101).unwrap().as_u32(), Some(480));
+            assert_eq!(ifd.entry(0x010F).unwrap().as_str(), Some("Camera"));
+            let values: Vec<_> = ifd.entry(0x014A).unwrap().u32_values().collect();
+            assert_eq!(values, [1, 2, 3]);
+            assert!(ifd.entry(0x0110).is_none());
+            assert!(ifd.next().is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_ifd() {
+        let mut w = TiffWriter::new(false);
+        let entries = vec![w.short(0x0100, &[640]), w.short(0x0101, &[480])];
+        let ifd0 = w.ifd(entries, 0);
+        let data = w.finish(ifd0);
+
+        assert!(Tiff::new(&data[..20]).unwrap().first_ifd().is_none());
+        assert!(Tiff::new(&data[..9]).unwrap().first_ifd().is_none());
+        assert!(Tiff::new(&data[..6]).unwrap().first_ifd().is_none());
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_values() {
+        let mut w = TiffWriter::new(false);
+        let entries = vec![
+            w.raw(0x010F, 2, 8, 0xFFFF),
+            w.raw(0x0110, 2, u32::MAX, 0),
+            w.raw(0x0111, 99, 1, 0),
+        ];
+        let ifd0 = w.ifd(entries, 0);
+        let data = w.finish(ifd0);
+        let ifd = Tiff::new(&data).unwrap().first_ifd().unwrap();
+
+        assert!(ifd.entry(0x010F).is_none());
+        assert!(ifd.entry(0x0110).is_none());
+        assert!(ifd.entry(0x0111).is_none());
+    }
+
+    #[test]
+    fn ignores_invalid_ifd_offsets() {
+        let mut w = TiffWriter::new(false);
+        let entries = vec![w.long(0x8769, &[0xFFFF_FFF0]), w.long(0x014A, &[0, 3])];
+        let ifd0 = w.ifd(entries, 0xFFFF);
+        let data = w.finish(ifd0);
+        let ifd = Tiff::new(&data).unwrap().first_ifd().unwrap();
+
+        assert!(ifd.sub_ifd(0x8769).is_none());
+        assert!(ifd.sub_ifds(0x014A).is_empty());
+        assert!(ifd.sub_ifds(0x0100).is_empty());
+        assert!(ifd.next().is_none());
+    }
+
+    #[test]
+    fn follows_ifd_references() {
+        let mut w = TiffWriter::new(true);
+        let entries = vec![w.short(0x0100, &[32])];
+        let sub_ifd = w.ifd(entries, 0);
+        let entries = vec![
+            w.long(0x8769, &[sub_ifd]),
+            w.long(0x014A, &[sub_ifd, sub_ifd]),
+        ];
+        let ifd0 = w.ifd(entries, sub_ifd);
+        let data = w.finish(ifd0);
+        let ifd = Tiff::new(&data).unwrap().first_ifd().unwrap();
+
+        let width = |ifd: Ifd| ifd.entry(0x0100).and_then(|e| e.as_u32());
+        assert_eq!(ifd.sub_ifd(0x8769).and_then(width), Some(32));
+        assert_eq!(ifd.sub_ifds(0x014A).len(), 2);
+        assert_eq!(ifd.next().and_then(width), Some(32));
+    }
+
+    #[test]
+    fn headless_offsets_are_relative_to_data() {
+        let mut w = TiffWriter::headless(b"prefix", true);
+        let entries = vec![w.ascii(0x0011, "identifier")];
+        let offset = w.ifd(entries, 0);
+        let data = w.into_bytes();
+        let ifd = Tiff::headless(&data, true).ifd_at(offset as usize).unwrap();
+
+        assert_eq!(ifd.entry(0x0011).unwrap().as_str(), Some("identifier"));
+        assert!(Tiff::headless(&data, true).ifd_at(0).is_none());
+    }
+
+    #[test]
+    fn bytes_are_bounds_checked() {
+        let tiff = Tiff::new(b"II\x2a\0\x08\0\0\0").unwrap();
+        assert_eq!(tiff.bytes(4, 4), Some(&b"\x08\0\0\0"[..]));
+        assert!(tiff.bytes(4, 5).is_none());
+        assert!(tiff.bytes(u32::MAX, u32::MAX).is_none());
+    }
+}
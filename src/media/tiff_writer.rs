@@ -0,0 +1,129 @@
+/// Writer of the TIFF structures used as fixtures by the parser tests.
+/// IFDs are appended with their values, so sub-IFDs have to be written before the parent ones
+pub struct TiffWriter {
+    data: Vec<u8>,
+    big_endian: bool,
+}
+
+pub struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+impl TiffWriter {
+    pub fn new(big_endian: bool) -> TiffWriter {
+        let header: &[u8] = match big_endian {
+            true => b"MM\0\x2a\0\0\0\0",
+            false => b"II\x2a\0\0\0\0\0",
+        };
+        TiffWriter::headless(header, big_endian)
+    }
+
+    /// A structure without the TIFF header that has the offsets relative to the prefix start
+    pub fn headless(prefix: &[u8], big_endian: bool) -> TiffWriter {
+        TiffWriter {
+            data: prefix.to_vec(),
+            big_endian,
+        }
+    }
+
+    pub fn short(&self, tag: u16, values: &[u16]) -> Entry {
+        let value = values.iter().flat_map(|v| self.u16_bytes(*v)).collect();
+        Entry::new(tag, 3, values.len() as u32, value)
+    }
+
+    pub fn long(&self, tag: u16, values: &[u32]) -> Entry {
+        let value = values.iter().flat_map(|v| self.u32_bytes(*v)).collect();
+        Entry::new(tag, 4, values.len() as u32, value)
+    }
+
+    pub fn ascii(&self, tag: u16, value: &str) -> Entry {
+        let mut value = value.as_bytes().to_vec();
+        value.push(0);
+        Entry::new(tag, 2, value.len() as u32, value)
+    }
+
+    pub fn undefined(&self, tag: u16, value: &[u8]) -> Entry {
+        Entry::new(tag, 7, value.len() as u32, value.to_vec())
+    }
+
+    /// An entry with the arbitrary type, count and value field (used for malformed entries)
+    pub fn raw(&self, tag: u16, kind: u16, count: u32, field: u32) -> Entry {
+        Entry::new(tag, kind, count, self.u32_bytes(field).to_vec())
+    }
+
+    /// Appends the data and returns its offset
+    pub fn blob(&mut self, data: &[u8]) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(data);
+        if !self.data.len().is_multiple_of(2) {
+            self.data.push(0);
+        }
+        offset
+    }
+
+    /// Appends the IFD followed by the values that don't fit into the entries and returns its offset
+    pub fn ifd(&mut self, entries: Vec<Entry>, next: u32) -> u32 {
+        let offset = self.data.len() as u32;
+        let mut value_offset = offset + 2 + entries.len() as u32 * 12 + 4;
+
+        self.data.extend(self.u16_bytes(entries.len() as u16));
+        for e in &entries {
+            self.data.extend(self.u16_bytes(e.tag));
+            self.data.extend(self.u16_bytes(e.kind));
+            self.data.extend(self.u32_bytes(e.count));
+            if e.value.len() <= 4 {
+                let mut field = [0; 4];
+                field[..e.value.len()].copy_from_slice(&e.value);
+                self.data.extend(field);
+            } else {
+                self.data.extend(self.u32_bytes(value_offset));
+                value_offset += e.value.len().next_multiple_of(2) as u32;
+            }
+        }
+        self.data.extend(self.u32_bytes(next));
+
+        for e in entries.iter().filter(|e| e.value.len() > 4) {
+            self.blob(&e.value);
+        }
+        offset
+    }
+
+    /// Writes the first IFD offset into the header
+    pub fn finish(mut self, first_ifd: u32) -> Vec<u8> {
+        let offset = self.u32_bytes(first_ifd);
+        self.data[4..8].copy_from_slice(&offset);
+        self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        }
+    }
+}
+
+impl Entry {
+    fn new(tag: u16, kind: u16, count: u32, value: Vec<u8>) -> Entry {
+        Entry {
+            tag,
+            kind,
+            count,
+            value,
+        }
+    }
+}
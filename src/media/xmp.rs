@@ -0,0 +1,163 @@
+use crate::exif_orientation::ExifOrientation;
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+
+/// A subset of the XMP packet properties used when a file has no EXIF data
+#[derive(Default)]
+pub struct XmpMetadata {
+    pub datetime: Option<DateTime<FixedOffset>>,
+    pub orientation: Option<ExifOrientation>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    /// Length of the video appended to the end of a motion photo
+    pub motion_video_length: Option<u64>,
+}
+
+const DATE_PROPERTIES: [&str; 3] = [
+    "exif:DateTimeOriginal",
+    "photoshop:DateCreated",
+    "xmp:CreateDate",
+];
+
+impl XmpMetadata {
+    /// Looks for the properties in both the attribute and the element forms without a full XML parsing
+    pub fn parse(packet: &[u8]) -> XmpMetadata {
+        let xmp = String::from_utf8_lossy(packet);
+
+        XmpMetadata {
+            datetime: DATE_PROPERTIES
+                .iter()
+                .find_map(|name| property(&xmp, name).and_then(parse_datetime)),
+            orientation: property(&xmp, "tiff:Orientation")
+                .and_then(|value| value.parse::<i64>().ok())
+                .and_then(|value| value.try_into().ok()),
+            camera_make: property(&xmp, "tiff:Make").map(String::from),
+            camera_model: property(&xmp, "tiff:Model").map(String::from),
+            motion_video_length: motion_video_length(&xmp),
+        }
+    }
+}
+
+/// Motion photos of the first version store the video length as `MicroVideoOffset`,
+/// the later ones describe the video as an item of the container directory
+fn motion_video_length(xmp: &str) -> Option<u64> {
+    if let Some(offset) = property(xmp, "GCamera:MicroVideoOffset") {
+        return offset.parse().ok().filter(|length| *length > 0);
+    }
+
+    let semantic = xmp.find("Item:Semantic=\"MotionPhoto\"")?;
+    let item_start = xmp[..semantic].rfind('<')?;
+    let item_end = semantic + xmp[semantic..].find('>')?;
+    attribute_value(&xmp[item_start..item_end], "Item:Length")?
+        .parse()
+        .ok()
+        .filter(|length| *length > 0)
+}
+
+fn property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let value = attribute_value(xmp, name).or_else(|| element_value(xmp, name))?;
+    Some(value.trim()).filter(|value| !value.is_empty())
+}
+
+fn attribute_value<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let start = xmp.find(&format!("{name}="))? + name.len() + 1;
+    let quote = xmp[start..]
+        .chars()
+        .next()
+        .filter(|c| *c == '"' || *c == '\'')?;
+    let value = &xmp[start + 1..];
+    value.find(quote).map(|end| &value[..end])
+}
+
+fn element_value<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let start = xmp.find(&format!("<{name}>"))? + name.len() + 2;
+    let value = &xmp[start..];
+    value.find(&format!("</{name}>")).map(|end| &value[..end])
+}
+
+/// XMP dates are ISO 8601 with an optional time zone. A missing zone is treated as UTC
+fn parse_datetime(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M%#z"))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|dt| dt.and_utc().fixed_offset())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_micro_video_offset() {
+        let xmp = r#"<rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="4096"/>"#;
+        assert_eq!(motion_video_length(xmp), Some(4096));
+
+        let xmp = "<GCamera:MicroVideoOffset> 2048 </GCamera:MicroVideoOffset>";
+        assert_eq!(motion_video_length(xmp), Some(2048));
+    }
+
+    #[test]
+    fn reads_container_item_length() {
+        let xmp = r#"<Container:Directory><rdf:Seq>
+            <rdf:li rdf:parseType="Resource">
+                <Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary" Item:Length="0"/>
+            </rdf:li>
+            <rdf:li rdf:parseType="Resource">
+                <Container:Item
+                    Item:Mime="video/mp4"
+                    Item:Semantic="MotionPhoto"
+                    Item:Length="123456"
+                    Item:Padding="0"/>
+            </rdf:li>
+        </rdf:Seq></Container:Directory>"#;
+        assert_eq!(motion_video_length(xmp), Some(123456));
+
+        let xmp = r#"<Container:Item Item:Length='42' Item:Semantic="MotionPhoto"/>"#;
+        assert_eq!(motion_video_length(xmp), Some(42));
+    }
+
+    #[test]
+    fn rejects_invalid_lengths() {
+        assert_eq!(motion_video_length(""), None);
+        assert_eq!(motion_video_length(r#"GCamera:MicroVideoOffset="0""#), None);
+        assert_eq!(
+            motion_video_length(r#"GCamera:MicroVideoOffset="-1""#),
+            None
+        );
+        assert_eq!(motion_video_length(r#"GCamera:MicroVideoOffset="12"#), None);
+        assert_eq!(motion_video_length(r#"GCamera:MicroVideoOffset=12"#), None);
+
+        // The length of another item is not used
+        let xmp =
+            r#"<Container:Item Item:Length="10"/><Container:Item Item:Semantic="MotionPhoto"/>"#;
+        assert_eq!(motion_video_length(xmp), None);
+        assert_eq!(
+            motion_video_length(r#"Item:Semantic="MotionPhoto" Item:Length="5""#),
+            None
+        );
+        assert_eq!(
+            motion_video_length(r#"<Item Item:Semantic="MotionPhoto" Item:Length="5""#),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_packet() {
+        let packet = br#"<x:xmpmeta><rdf:Description
+            exif:DateTimeOriginal="2024-06-01T12:30:00+02:00"
+            tiff:Orientation="6"
+            tiff:Make="Google"
+            GCamera:MicroVideoOffset="100"/></x:xmpmeta>"#;
+        let xmp = XmpMetadata::parse(packet);
+
+        let datetime = xmp.datetime.unwrap();
+        assert_eq!(datetime.to_rfc3339(), "2024-06-01T12:30:00+02:00");
+        assert_eq!(xmp.orientation.map(i64::from), Some(6));
+        assert_eq!(xmp.camera_make.as_deref(), Some("Google"));
+        assert!(xmp.camera_model.is_none());
+        assert_eq!(xmp.motion_video_length, Some(100));
+    }
+}
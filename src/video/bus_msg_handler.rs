@@ -1,5 +1,5 @@
 use super::pipeline_ext::PipelineStd;
-use super::SeekRequestBuffer;
+use super::{EosCallback, SeekRequestBuffer};
 use gstreamer::glib::WeakRef;
 use gstreamer::message::NeedContext;
 use gstreamer::prelude::*;
@@ -53,10 +53,11 @@ pub fn running_handler(
     gl_ctx: &GLContext,
     pipeline: &WeakRef<Pipeline>,
     seek_state: &Arc<Mutex<SeekRequestBuffer>>,
+    on_eos: &Arc<Mutex<Option<EosCallback>>>,
 ) -> BusSyncReply {
     match msg.view() {
         MessageView::NeedContext(nc) => provide_ctx(nc, msg.src(), gl_ctx),
-        _ => send_to_slint_event_loop(msg, pipeline, seek_state),
+        _ => send_to_slint_event_loop(msg, pipeline, seek_state, on_eos),
     }
     BusSyncReply::Drop
 }
@@ -94,15 +95,23 @@ fn send_to_slint_event_loop(
     msg: &Message,
     pipeline: &WeakRef<Pipeline>,
     seek_state: &Arc<Mutex<SeekRequestBuffer>>,
+    on_eos: &Arc<Mutex<Option<EosCallback>>>,
 ) {
     let callback = {
         let msg = msg.to_owned();
         let pipeline = pipeline.clone();
         let seek_state = seek_state.clone();
+        let on_eos = on_eos.clone();
         move || match msg.view() {
             MessageView::Eos(_) => {
                 seek_state.lock().unwrap().reset();
-                restart_pipeline(&pipeline);
+                let on_eos = on_eos.lock().unwrap().take();
+                match on_eos {
+                    Some(on_eos) => on_eos(),
+                    None => {
+                        restart_pipeline(&pipeline);
+                    }
+                }
             }
             MessageView::AsyncDone(_) => {
                 finish_seeking(&pipeline, &seek_state);
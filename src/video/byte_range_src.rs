@@ -0,0 +1,116 @@
+use crate::media::EmbeddedVideo;
+use gstreamer::prelude::*;
+use gstreamer::{Buffer, Element, Pipeline};
+use gstreamer_app::{AppSrc, AppSrcCallbacks, AppStreamType};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Makes `playbin` create an `appsrc` source that is configured in the `source-setup` signal
+pub const URI: &str = "appsrc://";
+
+/// Used when the source doesn't know how much data is needed
+const DEFAULT_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Feeds the `playbin` with the byte range of the file through a random-access `appsrc`,
+/// so the embedded video is played without extracting it
+pub fn setup(pipeline: &Pipeline, path: &Path, range: EmbeddedVideo) -> anyhow::Result<()> {
+    let reader = Arc::new(Mutex::new(RangeReader {
+        file: File::open(path)?,
+        range,
+        position: 0,
+    }));
+
+    pipeline.connect("source-setup", false, move |args| {
+        let src = args
+            .get(1)
+            .and_then(|value| value.get::<Element>().ok())
+            .and_then(|element| element.downcast::<AppSrc>().ok());
+
+        match src {
+            Some(src) => configure(&src, &reader),
+            None => log::error!("Unexpected playbin source for a byte range"),
+        }
+        None
+    });
+
+    Ok(())
+}
+
+fn configure(src: &AppSrc, reader: &Arc<Mutex<RangeReader>>) {
+    let length = reader.lock().unwrap().range.length;
+    src.set_stream_type(AppStreamType::RandomAccess);
+    src.set_size(length as i64);
+
+    let callbacks = AppSrcCallbacks::builder()
+        .need_data({
+            let reader = reader.clone();
+            move |src, size| {
+                let result = reader.lock().unwrap().read(size);
+                match result {
+                    Ok(Some(buffer)) => {
+                        let _ = src.push_buffer(buffer);
+                    }
+                    Ok(None) => {
+                        let _ = src.end_of_stream();
+                    }
+                    Err(e) => {
+                        log::error!("Failed to read the embedded video: {e}");
+                        let _ = src.end_of_stream();
+                    }
+                }
+            }
+        })
+        .seek_data({
+            let reader = reader.clone();
+            move |_src, offset| reader.lock().unwrap().seek(offset)
+        })
+        .build();
+
+    src.set_callbacks(callbacks);
+}
+
+struct RangeReader {
+    file: File,
+    range: EmbeddedVideo,
+    position: u64,
+}
+
+impl RangeReader {
+    /// Returns `None` at the end of the range
+    fn read(&mut self, size: u32) -> io::Result<Option<Buffer>> {
+        let remaining = self.range.length.saturating_sub(self.position);
+        if remaining == 0 {
+            return Ok(None);
+        }
+
+        let size = match size {
+            0 | u32::MAX => DEFAULT_CHUNK_SIZE,
+            size => u64::from(size),
+        };
+        let size = size.min(remaining);
+
+        let mut data = vec![0; size as usize];
+        self.file
+            .seek(SeekFrom::Start(self.range.offset + self.position))?;
+        self.file.read_exact(&mut data)?;
+
+        let mut buffer = Buffer::from_mut_slice(data);
+        if let Some(buffer) = buffer.get_mut() {
+            buffer.set_offset(self.position);
+        }
+        self.position += size;
+
+        Ok(Some(buffer))
+    }
+
+    fn seek(&mut self, offset: u64) -> bool {
+        if offset > self.range.length {
+            return false;
+        }
+
+        self.position = offset;
+        true
+    }
+}
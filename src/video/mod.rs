@@ -1,6 +1,7 @@
 use self::bus_msg_handler::{async_done_waiting_handler, running_handler, AsyncDoneWaiter};
 use self::framebuffer::FrameBuffer;
 use self::pipeline_ext::{PipelineOwned, PipelineStd};
+use crate::media::{EmbeddedVideo, VideoType};
 use anyhow::anyhow;
 use gl_context_slint::GLContextSlint;
 use gstreamer::{State, StateChangeSuccess};
@@ -12,6 +13,7 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 mod bus_msg_handler;
+mod byte_range_src;
 mod framebuffer;
 mod gl_context_slint;
 mod pipeline;
@@ -54,21 +56,45 @@ impl VideoLoader {
         })
     }
 
-    pub fn load(&self, path: &Path) -> anyhow::Result<Video> {
-        Video::new(path, &self.gl_ctx, self.request_redraw.clone())
+    pub fn load(&self, path: &Path, video_type: VideoType) -> anyhow::Result<Video> {
+        let deinterlace = video_type.may_be_interlaced();
+        Video::new(
+            path,
+            None,
+            deinterlace,
+            &self.gl_ctx,
+            self.request_redraw.clone(),
+        )
+    }
+
+    /// Loads the video embedded into a motion photo
+    pub fn load_embedded(&self, path: &Path, video: EmbeddedVideo) -> anyhow::Result<Video> {
+        Video::new(
+            path,
+            Some(video),
+            false,
+            &self.gl_ctx,
+            self.request_redraw.clone(),
+        )
     }
 }
 
+/// Called in the Slint event loop when the video reaches its end
+type EosCallback = Box<dyn FnOnce() + Send + 'static>;
+
 #[derive(Clone)]
 pub struct Video {
     pipeline: Arc<PipelineOwned>,
     fb: Arc<Mutex<FrameBuffer>>,
     seek_state: Arc<Mutex<SeekRequestBuffer>>,
+    on_eos: Arc<Mutex<Option<EosCallback>>>,
 }
 
 impl Video {
     fn new(
         path: &Path,
+        embedded: Option<EmbeddedVideo>,
+        deinterlace: bool,
         gl_ctx: &GLContext,
         request_redraw: Arc<dyn Fn() + Send + Sync + 'static>,
     ) -> anyhow::Result<Self> {
@@ -84,7 +110,7 @@ impl Video {
             }
         };
 
-        let pipeline = pipeline::create(path, handle_new_frame)?;
+        let pipeline = pipeline::create(path, embedded, deinterlace, handle_new_frame)?;
         let pipeline = Arc::new(PipelineOwned::new(pipeline));
         let bus = pipeline.bus().ok_or_else(|| anyhow!("No pipline bus"))?;
 
@@ -100,20 +126,28 @@ impl Video {
         }
 
         let seek_state = Arc::new(Mutex::new(SeekRequestBuffer::default()));
+        let on_eos = Arc::new(Mutex::new(None));
         bus.set_sync_handler({
             let gl_ctx = gl_ctx.clone();
             let pipeline = pipeline.downgrade();
             let seek_state = seek_state.clone();
-            move |_bus, msg| running_handler(msg, &gl_ctx, &pipeline, &seek_state)
+            let on_eos = on_eos.clone();
+            move |_bus, msg| running_handler(msg, &gl_ctx, &pipeline, &seek_state, &on_eos)
         });
 
         Ok(Self {
             pipeline,
             fb,
             seek_state,
+            on_eos,
         })
     }
 
+    /// Replaces the default rewinding to the start on the next end of stream
+    pub fn set_on_eos(&self, callback: impl FnOnce() + Send + 'static) {
+        *self.on_eos.lock().unwrap() = Some(Box::new(callback));
+    }
+
     pub fn current_frame_gl_ref(&self) -> Option<Image> {
         let mut fb = self.fb.lock().unwrap();
         fb.fetch_next_frame_data();
@@ -1,4 +1,6 @@
-use gstreamer::glib::filename_to_uri;
+use super::byte_range_src;
+use crate::media::EmbeddedVideo;
+use gstreamer::glib::{GString, filename_to_uri};
 use gstreamer::prelude::*;
 use gstreamer::{Buffer, ElementFactory, FlowError, FlowSuccess, Fraction, Pipeline, Sample};
 use gstreamer_app::{AppSink, AppSinkCallbacks};
@@ -6,7 +8,14 @@ use gstreamer_gl::{GLBaseMemory, GLSyncMeta, CAPS_FEATURE_MEMORY_GL_MEMORY};
 use gstreamer_video::{VideoCapsBuilder, VideoFormat, VideoInfo};
 use std::path::Path;
 
-pub fn create<F>(path: &Path, handle_new_frame: F) -> anyhow::Result<Pipeline>
+/// The `embedded` video is read from the byte range of the file instead of the whole file.
+/// The `deinterlace` flag enables a filter that processes only interlaced frames
+pub fn create<F>(
+    path: &Path,
+    embedded: Option<EmbeddedVideo>,
+    deinterlace: bool,
+    handle_new_frame: F,
+) -> anyhow::Result<Pipeline>
 where
     F: Fn(Buffer, VideoInfo) + Clone + Send + 'static,
 {
@@ -35,7 +44,10 @@ where
         .property("sink", &terminator)
         .build()?;
 
-    let uri = filename_to_uri(path, None)?;
+    let uri = match embedded {
+        Some(_) => GString::from(byte_range_src::URI),
+        None => filename_to_uri(path, None)?,
+    };
     let pipeline = ElementFactory::make("playbin3")
         .property("uri", uri)
         .property("video-sink", &glsink)
@@ -43,6 +55,20 @@ where
         .downcast::<Pipeline>()
         .unwrap();
 
+    if let Some(range) = embedded {
+        byte_range_src::setup(&pipeline, path, range)?;
+    }
+
+    if deinterlace {
+        match ElementFactory::make("deinterlace")
+            .property_from_str("mode", "auto")
+            .build()
+        {
+            Ok(filter) => pipeline.set_property("video-filter", &filter),
+            Err(e) => log::error!("Failed to create a deinterlace filter: {e}"),
+        }
+    }
+
     let callbacks = sample_callbacks(handle_new_frame);
     appsink.set_callbacks(callbacks);
 
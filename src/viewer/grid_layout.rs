@@ -0,0 +1,335 @@
+use crate::ui::GridPos;
+use slint::SharedString;
+use std::cell::RefCell;
+
+/// Positions of the gallery items split into groups. Each group starts from a new row
+/// under its header. Rows are counted without headers, and the grid calculates the
+/// coordinates from the rows, the headers above and its own item and header sizes
+#[derive(Default)]
+pub struct GridLayout {
+    groups: Vec<GridGroup>,
+    /// Number of item rows before each group and the total number at the end
+    /// for the last used column count
+    rows_before: RefCell<(usize, Vec<usize>)>,
+}
+
+pub struct GridGroup {
+    /// Index of the first item
+    pub offset: usize,
+    pub count: usize,
+    pub label: SharedString,
+}
+
+impl GridLayout {
+    pub fn new(groups: Vec<GridGroup>) -> Self {
+        Self {
+            groups,
+            rows_before: Default::default(),
+        }
+    }
+
+    pub fn group(&self, group_idx: usize) -> Option<&GridGroup> {
+        self.groups.get(group_idx)
+    }
+
+    /// Returns the index of the group that starts from the item
+    pub fn group_starting_at(&self, idx: usize) -> Option<usize> {
+        let group_idx = self.group_of(idx)?;
+        (self.groups[group_idx].offset == idx).then_some(group_idx)
+    }
+
+    pub fn item_pos(&self, idx: usize, cols: usize) -> GridPos {
+        let Some(group_idx) = self.group_of(idx) else {
+            return GridPos::default();
+        };
+
+        let cols = cols.max(1);
+        let local_idx = idx - self.groups[group_idx].offset;
+
+        GridPos {
+            col: (local_idx % cols) as i32,
+            row: (self.rows_before(group_idx, cols) + local_idx / cols) as i32,
+            headers: group_idx as i32 + 1,
+            first_row: local_idx < cols,
+        }
+    }
+
+    /// Returns the total number of rows and headers
+    pub fn size(&self, cols: usize) -> GridPos {
+        GridPos {
+            row: self.rows_before(self.groups.len(), cols.max(1)) as i32,
+            headers: self.groups.len() as i32,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the first item of the row at the vertical position.
+    /// The header belongs to the first row of its group
+    pub fn index_at(&self, y: f32, cols: usize, item_size: f32, header_size: f32) -> usize {
+        let cols = cols.max(1);
+        let item_size = item_size.max(1.0);
+        let group_top = |group_idx: usize| {
+            self.rows_before(group_idx, cols) as f32 * item_size + group_idx as f32 * header_size
+        };
+
+        let group_idx = partition_point(self.groups.len(), |group_idx| group_top(group_idx) <= y)
+            .saturating_sub(1);
+
+        let Some(group) = self.groups.get(group_idx) else {
+            return 0;
+        };
+
+        let local_y = (y - group_top(group_idx) - header_size).max(0.0);
+        let local_row = (local_y / item_size) as usize;
+        group.offset + (local_row * cols).min(group.count)
+    }
+
+    /// Returns the item in the same column of the row above (`delta < 0`) or below (`delta > 0`).
+    /// The last item of the row is returned if the row is shorter
+    pub fn row_neighbour(&self, idx: usize, cols: usize, delta: isize) -> usize {
+        let cols = cols.max(1);
+        let pos = self.item_pos(idx, cols);
+        let total_rows = self.rows_before(self.groups.len(), cols);
+
+        let Some(row) = (pos.row as usize)
+            .checked_add_signed(delta)
+            .filter(|row| *row < total_rows)
+        else {
+            return idx;
+        };
+
+        let group_idx = partition_point(self.groups.len(), |group_idx| {
+            self.rows_before(group_idx + 1, cols) <= row
+        });
+        let group = &self.groups[group_idx];
+
+        let local_row = row - self.rows_before(group_idx, cols);
+        let local_idx = (local_row * cols + pos.col as usize).min(group.count - 1);
+        group.offset + local_idx
+    }
+
+    /// Returns the first item of the next group (`delta > 0`), or the first item
+    /// of the current group or the previous one if the item is already the first (`delta < 0`)
+    pub fn group_start(&self, idx: usize, delta: isize) -> usize {
+        let Some(group_idx) = self.group_of(idx) else {
+            return idx;
+        };
+
+        let target_idx = if delta > 0 {
+            group_idx.checked_add(1)
+        } else if idx > self.groups[group_idx].offset {
+            Some(group_idx)
+        } else {
+            group_idx.checked_sub(1)
+        };
+
+        target_idx
+            .and_then(|group_idx| self.groups.get(group_idx))
+            .map_or(idx, |group| group.offset)
+    }
+
+    fn group_of(&self, idx: usize) -> Option<usize> {
+        let group_idx = self
+            .groups
+            .partition_point(|group| group.offset <= idx)
+            .checked_sub(1)?;
+
+        let group = &self.groups[group_idx];
+        (idx < group.offset + group.count).then_some(group_idx)
+    }
+
+    fn rows_before(&self, group_idx: usize, cols: usize) -> usize {
+        let mut rows_before = self.rows_before.borrow_mut();
+        let (cached_cols, rows) = &mut *rows_before;
+
+        if *cached_cols != cols || rows.len() != self.groups.len() + 1 {
+            *cached_cols = cols;
+            rows.clear();
+            rows.push(0);
+
+            let mut total = 0;
+            for group in &self.groups {
+                total += group.count.div_ceil(cols);
+                rows.push(total);
+            }
+        }
+
+        rows[group_idx]
+    }
+}
+
+/// Returns the first index in `0..len` for which the predicate is `false`.
+/// The predicate should be `true` for the indices before it and `false` after it
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if pred(mid) {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ITEM: f32 = 100.0;
+    const HEADER: f32 = 20.0;
+
+    /// Groups of 5, 1 and 7 items
+    fn layout() -> GridLayout {
+        let groups = [(0, 5), (5, 1), (6, 7)]
+            .into_iter()
+            .map(|(offset, count)| GridGroup {
+                offset,
+                count,
+                label: Default::default(),
+            })
+            .collect();
+        GridLayout::new(groups)
+    }
+
+    fn pos(col: i32, row: i32, headers: i32, first_row: bool) -> GridPos {
+        GridPos {
+            col,
+            row,
+            headers,
+            first_row,
+        }
+    }
+
+    #[test]
+    fn places_groups_on_new_rows() {
+        let layout = layout();
+
+        assert_eq!(layout.item_pos(2, 3), pos(2, 0, 1, true));
+        assert_eq!(layout.item_pos(4, 3), pos(1, 1, 1, false));
+        assert_eq!(layout.item_pos(5, 3), pos(0, 2, 2, true));
+        assert_eq!(layout.item_pos(12, 3), pos(0, 5, 3, false));
+        assert_eq!(layout.item_pos(13, 3), GridPos::default());
+        assert_eq!(layout.size(3), pos(0, 6, 3, false));
+
+        assert_eq!(layout.item_pos(12, 1), pos(0, 12, 3, false));
+        assert_eq!(layout.size(1), pos(0, 13, 3, false));
+        assert_eq!(layout.size(0), layout.size(1));
+    }
+
+    #[test]
+    fn rows_before_follow_the_column_count() {
+        let layout = layout();
+
+        let rows = |cols| {
+            (0..=3)
+                .map(|g| layout.rows_before(g, cols))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rows(3), [0, 2, 3, 6]);
+        assert_eq!(rows(1), [0, 5, 6, 13]);
+        assert_eq!(rows(3), [0, 2, 3, 6]);
+        assert_eq!(rows(10), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn finds_row_starts_at_positions() {
+        let layout = layout();
+        let index_at = |y| layout.index_at(y, 3, ITEM, HEADER);
+
+        // Group tops are at 0, 220 and 340, the headers belong to the first rows
+        let expected = [
+            (-5.0, 0),
+            (0.0, 0),
+            (20.0, 0),
+            (119.0, 0),
+            (120.0, 3),
+            (219.0, 3),
+            (220.0, 5),
+            (339.0, 5),
+            (340.0, 6),
+            (460.0, 9),
+            (560.0, 12),
+            // The end of the last group is the item count
+            (660.0, 13),
+            (900.0, 13),
+        ];
+        for (y, idx) in expected {
+            assert_eq!(index_at(y), idx, "y = {y}");
+        }
+
+        assert_eq!(layout.index_at(650.0, 1, ITEM, HEADER), 6);
+        assert_eq!(layout.index_at(760.0, 1, ITEM, HEADER), 7);
+    }
+
+    #[test]
+    fn moves_between_rows() {
+        let layout = layout();
+        let expected = [
+            (0, -1, 0),
+            (0, 1, 3),
+            // The last row of the group is shorter
+            (2, 1, 4),
+            (4, 1, 5),
+            // Single-item group
+            (5, 1, 6),
+            (5, -1, 3),
+            (8, -1, 5),
+            (11, 1, 12),
+            (12, -1, 9),
+            (12, 1, 12),
+        ];
+        for (idx, delta, neighbour) in expected {
+            let actual = layout.row_neighbour(idx, 3, delta);
+            assert_eq!(actual, neighbour, "{idx} {delta:+}");
+        }
+
+        assert_eq!(layout.row_neighbour(4, 1, 1), 5);
+        assert_eq!(layout.row_neighbour(6, 1, -1), 5);
+        assert_eq!(layout.row_neighbour(0, 1, 12), 12);
+        assert_eq!(layout.row_neighbour(0, 1, 13), 0);
+    }
+
+    #[test]
+    fn moves_between_groups() {
+        let layout = layout();
+        let expected = [
+            (0, 1, 5),
+            (3, 1, 5),
+            (5, 1, 6),
+            (12, 1, 12),
+            (0, -1, 0),
+            (3, -1, 0),
+            (5, -1, 0),
+            (6, -1, 5),
+            (12, -1, 6),
+            (13, 1, 13),
+        ];
+        for (idx, delta, start) in expected {
+            assert_eq!(layout.group_start(idx, delta), start, "{idx} {delta:+}");
+        }
+    }
+
+    #[test]
+    fn handles_empty_layout() {
+        let layout = GridLayout::default();
+
+        assert_eq!(layout.item_pos(0, 3), GridPos::default());
+        assert_eq!(layout.size(3), GridPos::default());
+        assert_eq!(layout.index_at(100.0, 3, ITEM, HEADER), 0);
+        assert_eq!(layout.row_neighbour(0, 3, 1), 0);
+        assert_eq!(layout.group_start(0, 1), 0);
+        assert_eq!(layout.group_starting_at(0), None);
+    }
+
+    #[test]
+    fn finds_partition_points() {
+        assert_eq!(partition_point(0, |_| true), 0);
+        assert_eq!(partition_point(5, |_| true), 5);
+        assert_eq!(partition_point(5, |_| false), 0);
+        for point in 0..=7 {
+            assert_eq!(partition_point(7, |idx| idx < point), point);
+        }
+    }
+}
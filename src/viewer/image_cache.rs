@@ -0,0 +1,177 @@
+use crate::image_loader::ImageSize;
+use slint::{Rgb8Pixel, SharedPixelBuffer};
+use std::collections::{HashSet, VecDeque};
+
+/// Decoded viewer images keyed by the media index.
+/// The least recently used images are dropped when the capacity is exceeded
+pub struct ImageCache {
+    capacity: usize,
+    entries: VecDeque<CacheEntry>,
+    prefetching: HashSet<usize>,
+    generation: u64,
+}
+
+struct CacheEntry {
+    idx: usize,
+    size: ImageSize,
+    buffer: SharedPixelBuffer<Rgb8Pixel>,
+}
+
+impl ImageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity + 1),
+            prefetching: HashSet::new(),
+            generation: 0,
+        }
+    }
+
+    /// Changes each time the cache is cleared, so the images decoded before are not inserted
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the image decoded for the size and marks it as the most recently used
+    pub fn get(&mut self, idx: usize, size: ImageSize) -> Option<SharedPixelBuffer<Rgb8Pixel>> {
+        let pos = self
+            .entries
+            .iter()
+            .position(|e| e.idx == idx && e.size == size)?;
+
+        let entry = self.entries.remove(pos)?;
+        let buffer = entry.buffer.clone();
+        self.entries.push_back(entry);
+
+        Some(buffer)
+    }
+
+    pub fn insert(
+        &mut self,
+        generation: u64,
+        idx: usize,
+        size: ImageSize,
+        buffer: SharedPixelBuffer<Rgb8Pixel>,
+    ) {
+        if generation != self.generation {
+            return;
+        }
+
+        self.entries.retain(|e| e.idx != idx);
+        self.entries.push_back(CacheEntry { idx, size, buffer });
+
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns `false` if the image is already cached or being prefetched.
+    /// Full size images are never prefetched because of their memory footprint
+    pub fn start_prefetch(&mut self, idx: usize, size: ImageSize) -> bool {
+        if size == ImageSize::Full {
+            return false;
+        }
+
+        let is_cached = self.entries.iter().any(|e| e.idx == idx && e.size == size);
+        !is_cached && self.prefetching.insert(idx)
+    }
+
+    pub fn finish_prefetch(&mut self, generation: u64, idx: usize) {
+        if generation == self.generation {
+            self.prefetching.remove(&idx);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.prefetching.clear();
+        self.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: ImageSize = ImageSize::Fit(1920, 1080);
+
+    fn buffer(width: u32) -> SharedPixelBuffer<Rgb8Pixel> {
+        SharedPixelBuffer::new(width, 1)
+    }
+
+    fn cached_width(cache: &mut ImageCache, idx: usize, size: ImageSize) -> Option<u32> {
+        cache.get(idx, size).map(|buffer| buffer.width())
+    }
+
+    #[test]
+    fn gets_images_of_the_same_size() {
+        let mut cache = ImageCache::new(2);
+        cache.insert(0, 1, SIZE, buffer(10));
+
+        assert_eq!(cached_width(&mut cache, 1, SIZE), Some(10));
+        assert_eq!(cached_width(&mut cache, 1, ImageSize::Fit(100, 100)), None);
+        assert_eq!(cached_width(&mut cache, 2, SIZE), None);
+
+        // Another size replaces the image of the same index
+        cache.insert(0, 1, ImageSize::Fit(100, 100), buffer(20));
+        assert_eq!(cached_width(&mut cache, 1, SIZE), None);
+        assert_eq!(
+            cached_width(&mut cache, 1, ImageSize::Fit(100, 100)),
+            Some(20)
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ImageCache::new(2);
+        cache.insert(0, 1, SIZE, buffer(1));
+        cache.insert(0, 2, SIZE, buffer(2));
+        cache.get(1, SIZE);
+        cache.insert(0, 3, SIZE, buffer(3));
+
+        assert_eq!(cached_width(&mut cache, 1, SIZE), Some(1));
+        assert_eq!(cached_width(&mut cache, 2, SIZE), None);
+        assert_eq!(cached_width(&mut cache, 3, SIZE), Some(3));
+    }
+
+    #[test]
+    fn ignores_images_of_old_generations() {
+        let mut cache = ImageCache::new(2);
+        let generation = cache.generation();
+        cache.insert(generation, 1, SIZE, buffer(1));
+        cache.clear();
+
+        assert_eq!(cached_width(&mut cache, 1, SIZE), None);
+        cache.insert(generation, 2, SIZE, buffer(2));
+        assert_eq!(cached_width(&mut cache, 2, SIZE), None);
+
+        cache.insert(cache.generation(), 2, SIZE, buffer(2));
+        assert_eq!(cached_width(&mut cache, 2, SIZE), Some(2));
+    }
+
+    #[test]
+    fn prefetches_each_image_once() {
+        let mut cache = ImageCache::new(2);
+        let generation = cache.generation();
+
+        assert!(cache.start_prefetch(1, SIZE));
+        assert!(!cache.start_prefetch(1, SIZE));
+        cache.insert(generation, 1, SIZE, buffer(1));
+        cache.finish_prefetch(generation, 1);
+        assert!(!cache.start_prefetch(1, SIZE));
+        assert!(cache.start_prefetch(1, ImageSize::Fit(100, 100)));
+
+        // Prefetching of the old generation does not block the new one
+        assert!(cache.start_prefetch(2, SIZE));
+        cache.clear();
+        assert!(cache.start_prefetch(2, SIZE));
+        cache.finish_prefetch(generation, 2);
+        assert!(!cache.start_prefetch(2, SIZE));
+    }
+
+    #[test]
+    fn does_not_prefetch_full_size() {
+        let mut cache = ImageCache::new(2);
+        assert!(!cache.start_prefetch(1, ImageSize::Full));
+    }
+}
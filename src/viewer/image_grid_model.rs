@@ -1,60 +1,124 @@
-use crate::db::IndexDb;
-use crate::media::MediaType;
-use crate::ui::ImageGridItem;
-use crate::util;
+use super::grid_layout::{GridGroup, GridLayout};
+use super::thumbnail_loader::{LoadedThumbnail, ThumbnailLoader};
+use crate::config::{GalleryConfig, TimelineGrouping};
+use crate::db::{GroupKey, IndexDb};
+use crate::ui::{ImageGridItem, PhotoFlowApp};
 use anyhow::anyhow;
-use image::ImageDecoder;
-use image::codecs::jpeg::JpegDecoder;
-use slint::{Image, Model, ModelNotify, ModelTracker, Rgb8Pixel, SharedPixelBuffer, SharedString};
+use slint::{Image, Model, ModelNotify, ModelTracker, SharedString, Weak};
 use std::any::Any;
-use std::cell::RefCell;
-use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::io::Cursor;
+use std::cell::{Cell, Ref, RefCell};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, VecDeque};
+use std::ops;
 use std::sync::{Arc, Mutex};
 
 pub struct ImageGridModel {
     inner: RefCell<ViewModelInner>,
     notify: ModelNotify,
+    selected_media_id: Cell<Option<i64>>,
 }
 
 impl ImageGridModel {
-    pub fn new(db: Arc<Mutex<IndexDb>>) -> ImageGridModel {
-        ImageGridModel {
-            inner: RefCell::new(ViewModelInner::new(db)),
+    pub fn new(
+        db: Arc<Mutex<IndexDb>>,
+        weak_app: Weak<PhotoFlowApp>,
+        config: GalleryConfig,
+    ) -> anyhow::Result<ImageGridModel> {
+        Ok(ImageGridModel {
+            inner: RefCell::new(ViewModelInner::new(db, weak_app, config)?),
             notify: Default::default(),
-        }
+            selected_media_id: Default::default(),
+        })
     }
 
     pub fn set_range(&self, offset: usize, len: usize) {
         self.inner.borrow_mut().set_range(offset, len, &self.notify)
     }
+
+    pub fn layout(&self) -> Ref<'_, GridLayout> {
+        Ref::map(self.inner.borrow(), |inner| &inner.layout)
+    }
+
+    /// Remembers the selected media to find it again after the order table is rebuilt
+    pub fn track_selection(&self, selected_idx: usize) {
+        let id = self.inner.borrow().get_media_id(selected_idx);
+        self.selected_media_id.set(id);
+    }
+
+    /// Reloads the visible entries after the index is updated.
+    /// Returns the new item count and the new index of the selected media
+    pub fn reload(&self, selected_idx: usize) -> anyhow::Result<(usize, usize)> {
+        let mut inner = self.inner.borrow_mut();
+        let (count, selected_idx) =
+            inner.get_count_and_index(self.selected_media_id.get(), selected_idx)?;
+        let layout = load_layout(&inner.db, inner.grouping)?;
+        inner.reload(count, layout, &self.notify);
+
+        Ok((count, selected_idx))
+    }
+
+    /// Replaces the placeholder of a visible row with the loaded thumbnail
+    pub fn set_thumbnail(&self, db_idx: usize, generation: u64, thumbnail: LoadedThumbnail) {
+        let entry = ModelEntry {
+            image: Image::from_rgb8(thumbnail.image),
+            video_duration: thumbnail.video_duration,
+            is_live: thumbnail.is_live,
+            is_loaded: true,
+        };
+        let row = self.inner.borrow_mut().set_entry(db_idx, generation, entry);
+        if let Some(row) = row {
+            self.notify.row_changed(row);
+        }
+    }
+
+    /// Drops the outdated thumbnail of a row that failed to load.
+    /// The entry is not requested again until the next reload
+    pub fn reset_thumbnail(&self, db_idx: usize, generation: u64) {
+        let entry = ModelEntry {
+            is_loaded: true,
+            ..Default::default()
+        };
+        let row = self.inner.borrow_mut().set_entry(db_idx, generation, entry);
+        if let Some(row) = row {
+            self.notify.row_changed(row);
+        }
+    }
 }
 
 impl Model for ImageGridModel {
     type Data = ImageGridItem;
 
     fn row_count(&self) -> usize {
-        self.inner.borrow().range.length
+        self.inner.borrow().rows.len()
     }
 
     fn row_data(&self, row: usize) -> Option<Self::Data> {
         let inner = self.inner.borrow();
 
-        if row >= inner.range.length {
-            return None;
-        }
-
-        let db_index = inner.range.offset + row;
-
-        let index = db_index as i32;
-        let entry = inner.entries.get(&db_index).cloned().unwrap_or_default();
+        let data = match *inner.rows.get(row)? {
+            Row::Header(group_idx) => {
+                let group = inner.layout.group(group_idx)?;
+                Self::Data {
+                    index: group.offset as i32,
+                    is_header: true,
+                    header_text: group.label.clone(),
+                    header_count: group.count as i32,
+                    ..Default::default()
+                }
+            }
+            Row::Item(db_idx) => {
+                let entry = inner.entries.get(&db_idx).cloned().unwrap_or_default();
+                Self::Data {
+                    index: db_idx as i32,
+                    image: entry.image,
+                    video_duration: entry.video_duration.unwrap_or_default(),
+                    is_live: entry.is_live,
+                    ..Default::default()
+                }
+            }
+        };
 
-        Some(Self::Data {
-            index,
-            image: entry.image,
-            video_duration: entry.video_duration.unwrap_or_default(),
-        })
+        Some(data)
     }
 
     fn model_tracker(&self) -> &dyn ModelTracker {
@@ -69,15 +133,44 @@ impl Model for ImageGridModel {
 struct ViewModelInner {
     db: Arc<Mutex<IndexDb>>,
     range: Range,
+    item_count: usize,
+
+    grouping: TimelineGrouping,
+    layout: GridLayout,
+    /// Model rows of the visible range with the headers of the groups starting in it
+    rows: VecDeque<Row>,
 
+    /// Entries of the visible rows, the prefetch margins and the retained rows
     entries: HashMap<usize, ModelEntry>,
-    decoding_buf: Vec<u8>,
+    loader: ThumbnailLoader,
+
+    prefetch_screens: usize,
+    direction: ScrollDirection,
+    /// The last non-empty range, it is kept when the grid is hidden
+    last_range: Range,
+    /// Loaded entries outside of the prefetch window from the least recently evicted
+    retained: VecDeque<usize>,
 }
 
 #[derive(Default, Clone)]
 struct ModelEntry {
     image: Image,
     video_duration: Option<SharedString>,
+    is_live: bool,
+    is_loaded: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Row {
+    Header(usize),
+    Item(usize),
+}
+
+#[derive(Default, Clone, Copy)]
+enum ScrollDirection {
+    Up,
+    #[default]
+    Down,
 }
 
 #[derive(Default, Eq, PartialEq)]
@@ -87,21 +180,58 @@ struct Range {
 }
 
 impl ViewModelInner {
-    pub fn new(db: Arc<Mutex<IndexDb>>) -> ViewModelInner {
-        ViewModelInner {
+    pub fn new(
+        db: Arc<Mutex<IndexDb>>,
+        weak_app: Weak<PhotoFlowApp>,
+        config: GalleryConfig,
+    ) -> anyhow::Result<ViewModelInner> {
+        let item_count = db
+            .lock()
+            .ok()
+            .and_then(|db| db.get_item_count().ok())
+            .unwrap_or_default();
+
+        let layout = load_layout(&db, config.grouping)
+            .inspect_err(|e| log::error!("Failed to load gallery groups: {e}"))
+            .unwrap_or_default();
+
+        Ok(ViewModelInner {
+            loader: ThumbnailLoader::new(db.clone(), weak_app)?,
             db,
             range: Default::default(),
+            item_count: item_count as usize,
+            grouping: config.grouping,
+            layout,
+            rows: Default::default(),
             entries: Default::default(),
-            decoding_buf: Default::default(),
-        }
+            prefetch_screens: config.prefetch_screens,
+            direction: Default::default(),
+            last_range: Default::default(),
+            retained: Default::default(),
+        })
     }
 
     pub fn set_range(&mut self, offset: usize, length: usize, notify: &ModelNotify) {
         let new_range = Range { offset, length };
 
-        if self.range != new_range {
-            self.rebuild(&new_range, notify);
+        if self.range == new_range {
+            return;
+        }
+
+        if !self.range.is_empty() && !new_range.is_empty() {
+            self.direction = match new_range.offset.cmp(&self.range.offset) {
+                Ordering::Less => ScrollDirection::Up,
+                Ordering::Equal => self.direction,
+                Ordering::Greater => ScrollDirection::Down,
+            };
+        }
+
+        if !new_range.is_empty() {
+            self.last_range = Range { offset, length };
         }
+
+        self.rebuild(&new_range, notify);
+        self.update_window();
     }
 
     fn rebuild(&mut self, new_range: &Range, notify: &ModelNotify) {
@@ -139,104 +269,236 @@ impl ViewModelInner {
         };
     }
 
+    fn reload(&mut self, item_count: usize, layout: GridLayout, notify: &ModelNotify) {
+        self.loader.reset();
+        self.item_count = item_count;
+        self.layout = layout;
+
+        // The grid reports the actual range after the item count update
+        let max_length = item_count.saturating_sub(self.range.offset);
+        self.range.length = self.range.length.min(max_length);
+
+        self.rows.clear();
+        for db_idx in self.range.offset..self.range.end() {
+            self.rows.extend(item_rows(&self.layout, db_idx));
+        }
+
+        // Indices are changed, so only the visible entries are kept.
+        // Their outdated thumbnails are shown until the new ones are loaded (or fail to load)
+        // to avoid blinking
+        let range = &self.range;
+        self.entries.retain(|db_idx, _| range.contains(*db_idx));
+        self.entries.values_mut().for_each(|e| e.is_loaded = false);
+        self.retained.clear();
+        self.update_window();
+
+        notify.reset();
+    }
+
+    fn get_media_id(&self, db_idx: usize) -> Option<i64> {
+        let db = self.db.lock().ok()?;
+        db.get_media_id(db_idx as i64).ok()
+    }
+
+    fn get_count_and_index(
+        &self,
+        media_id: Option<i64>,
+        fallback_idx: usize,
+    ) -> anyhow::Result<(usize, usize)> {
+        let db = self.db.lock().map_err(|_| anyhow!("Failed to lock DB"))?;
+        let count = db.get_item_count()? as usize;
+
+        let idx = match media_id {
+            Some(id) => db.get_index_of_media(id)?.map(|idx| idx as usize),
+            None => None,
+        };
+
+        let idx = idx.unwrap_or(fallback_idx).min(count.saturating_sub(1));
+        Ok((count, idx))
+    }
+
     fn clear(&mut self, notify: &ModelNotify) {
-        let remove_count = self.range.length;
+        let remove_count = self.rows.len();
 
         self.range = Range::default();
-        self.entries.clear();
+        self.rows.clear();
 
         notify.row_removed(0, remove_count);
     }
 
     fn add_front(&mut self, count: usize, notify: &ModelNotify) {
-        let start_db_idx = self.range.offset + self.range.length;
+        let start_db_idx = self.range.end();
+        let start_row = self.rows.len();
 
-        for i in 0..count {
-            self.load_entry(start_db_idx + i);
+        for db_idx in start_db_idx..start_db_idx + count {
+            self.rows.extend(item_rows(&self.layout, db_idx));
         }
 
         self.range.length += count;
-        notify.row_added(self.range.length - count, count);
+        notify.row_added(start_row, self.rows.len() - start_row);
     }
 
     fn remove_front(&mut self, count: usize, notify: &ModelNotify) {
-        let start_db_idx = self.range.offset + self.range.length - count;
+        let start_db_idx = self.range.end() - count;
+        let row_count = self.row_count_of(start_db_idx, count);
 
-        for i in 0..count {
-            self.entries.remove(&(start_db_idx + i));
-        }
+        self.rows.truncate(self.rows.len() - row_count);
 
         self.range.length -= count;
-        notify.row_removed(self.range.length, count);
+        notify.row_removed(self.rows.len(), row_count);
     }
 
     fn add_back(&mut self, count: usize, notify: &ModelNotify) {
         let start_db_idx = self.range.offset - count;
+        let rows = (start_db_idx..self.range.offset)
+            .flat_map(|db_idx| item_rows(&self.layout, db_idx))
+            .collect::<Vec<_>>();
 
-        for i in 0..count {
-            self.load_entry(start_db_idx + i);
+        for row in rows.iter().rev() {
+            self.rows.push_front(*row);
         }
 
         self.range.offset -= count;
         self.range.length += count;
-        notify.row_added(0, count);
+        notify.row_added(0, rows.len());
     }
 
     fn remove_back(&mut self, count: usize, notify: &ModelNotify) {
-        let start_db_idx = self.range.offset;
+        let row_count = self.row_count_of(self.range.offset, count);
 
-        for i in 0..count {
-            self.entries.remove(&(start_db_idx + i));
-        }
+        self.rows.drain(..row_count);
 
         self.range.offset += count;
         self.range.length -= count;
-        notify.row_removed(0, count);
+        notify.row_removed(0, row_count);
     }
 
-    fn load_entry(&mut self, db_idx: usize) {
-        match self.get_entry(db_idx) {
-            Ok(t) => {
-                self.entries.insert(db_idx, t);
+    /// Number of the model rows of the items including the group headers
+    fn row_count_of(&self, start_db_idx: usize, count: usize) -> usize {
+        (start_db_idx..start_db_idx + count)
+            .map(|db_idx| item_rows(&self.layout, db_idx).count())
+            .sum()
+    }
+
+    /// Requests the thumbnails of the visible rows and the prefetch margins.
+    /// Loaded entries outside of them are retained, placeholders are dropped
+    fn update_window(&mut self) {
+        let window = self.prefetch_window();
+
+        // The entries closest to the last visible rows are evicted last
+        let mut evicted = self
+            .entries
+            .iter()
+            .filter(|(db_idx, _)| !window.contains(db_idx))
+            .map(|(db_idx, entry)| (*db_idx, entry.is_loaded))
+            .collect::<Vec<_>>();
+        evicted.sort_by_key(|(db_idx, _)| Reverse(self.last_range.distance(*db_idx)));
+
+        for (db_idx, is_loaded) in evicted {
+            if !is_loaded {
+                self.entries.remove(&db_idx);
+                self.loader.cancel(db_idx);
+            } else if !self.retained.contains(&db_idx) {
+                self.retained.push_back(db_idx);
             }
-            Err(e) => {
-                log::error!("Failed to get thumbnail ({db_idx}): {e}");
+        }
+
+        // Keep about a screen of the recently evicted rows
+        self.retained.retain(|db_idx| !window.contains(db_idx));
+        while self.retained.len() > self.last_range.length {
+            if let Some(db_idx) = self.retained.pop_front() {
+                self.entries.remove(&db_idx);
             }
-        };
+        }
+
+        for db_idx in self.load_order(&window) {
+            let entry = self.entries.entry(db_idx).or_default();
+            if !entry.is_loaded {
+                self.loader.request(db_idx);
+            }
+        }
     }
 
-    fn get_entry(&mut self, db_idx: usize) -> anyhow::Result<ModelEntry> {
-        let (path, metadata, thumbnail) = {
-            let db = self.db.lock().map_err(|_| anyhow!("Failed to lock DB"))?;
-            db.get_path_metadata_and_thumbnail(db_idx as i64)?
+    /// The visible range extended by the margins. The margin ahead of the scroll direction
+    /// is `prefetch_screens` times the range length, the margin behind is a quarter of it
+    fn prefetch_window(&self) -> ops::Range<usize> {
+        if self.range.is_empty() {
+            return 0..0;
+        }
+
+        let ahead = self.range.length * self.prefetch_screens;
+        let behind = ahead / 4;
+        let (before, after) = match self.direction {
+            ScrollDirection::Up => (ahead, behind),
+            ScrollDirection::Down => (behind, ahead),
         };
 
-        let decoder = JpegDecoder::new(Cursor::new(thumbnail))?;
-        let (w, h) = decoder.dimensions();
-        let required_len = decoder.total_bytes() as usize;
+        let start = self.range.offset.saturating_sub(before);
+        let end = (self.range.end() + after).min(self.item_count.max(self.range.end()));
+        start..end
+    }
 
-        self.decoding_buf.reserve_exact(required_len);
-        self.decoding_buf.resize(required_len, 0);
+    /// Visible rows go first, then the rows ahead of the scroll direction and the rows behind it
+    fn load_order(&self, window: &ops::Range<usize>) -> Vec<usize> {
+        let visible = self.range.offset..self.range.end();
+        let above = (window.start..visible.start).rev();
+        let below = visible.end..window.end;
 
-        decoder.read_image(&mut self.decoding_buf)?;
+        match self.direction {
+            ScrollDirection::Up => visible.rev().chain(above).chain(below).collect(),
+            ScrollDirection::Down => visible.chain(below).chain(above).collect(),
+        }
+    }
 
-        let buf = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(&self.decoding_buf, w, h);
-        let image = Image::from_rgb8(buf);
+    /// Returns the row of the updated entry
+    fn set_entry(&mut self, db_idx: usize, generation: u64, entry: ModelEntry) -> Option<usize> {
+        if !self.loader.accept(db_idx, generation) {
+            return None;
+        }
 
-        let video_duration = MediaType::from_path(&path)
-            .and_then(|media_type| match media_type {
-                MediaType::Image(_) => None,
-                MediaType::Video(_) => Some(metadata),
-            })
-            .map(|duration_ms| util::hh_mm_ss(duration_ms as u64).into());
+        *self.entries.get_mut(&db_idx)? = entry;
 
-        Ok(ModelEntry {
-            image,
-            video_duration,
-        })
+        // Prefetched and retained entries are not the model rows
+        self.rows.iter().position(|row| *row == Row::Item(db_idx))
     }
 }
 
+/// The first item of a group is preceded by the group header
+fn item_rows(layout: &GridLayout, db_idx: usize) -> impl Iterator<Item = Row> {
+    let header = layout.group_starting_at(db_idx).map(Row::Header);
+    header.into_iter().chain([Row::Item(db_idx)])
+}
+
+fn load_layout(db: &Mutex<IndexDb>, grouping: TimelineGrouping) -> anyhow::Result<GridLayout> {
+    let groups = {
+        let db = db.lock().map_err(|_| anyhow!("Failed to lock DB"))?;
+        db.get_groups(grouping)?
+    };
+
+    let date_format = match grouping {
+        TimelineGrouping::Day => "%A, %-d %B %Y",
+        TimelineGrouping::Month => "%B %Y",
+        TimelineGrouping::Year => "%Y",
+    };
+
+    let groups = groups
+        .into_iter()
+        .map(|group| GridGroup {
+            offset: group.offset,
+            count: group.count,
+            label: match group.key {
+                GroupKey::Date(date) => date.format(date_format).to_string().into(),
+                GroupKey::SourceDate(source, date) => {
+                    format!("{} · {source}", date.format(date_format)).into()
+                }
+                GroupKey::Letter(letter) => letter.into(),
+            },
+        })
+        .collect();
+
+    Ok(GridLayout::new(groups))
+}
+
 impl Range {
     pub fn is_empty(&self) -> bool {
         self.length == 0
@@ -249,4 +511,21 @@ impl Range {
     pub fn max(&self) -> usize {
         self.offset + self.length - 1
     }
+
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+
+    pub fn contains(&self, idx: usize) -> bool {
+        idx >= self.offset && idx < self.end()
+    }
+
+    /// Number of items between the index and the range
+    pub fn distance(&self, idx: usize) -> usize {
+        if idx < self.offset {
+            self.offset - idx
+        } else {
+            (idx + 1).saturating_sub(self.end())
+        }
+    }
 }
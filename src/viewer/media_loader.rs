@@ -1,17 +1,30 @@
-use crate::db::IndexDb;
-use crate::exif_orientation::ExifOrientation;
-use crate::image_loader;
-use crate::media::{Media, MediaType};
+use super::image_cache::ImageCache;
+use crate::db::{IndexDb, MediaEntry};
+use crate::image_loader::{self, ImageSize};
+use crate::media::{Media, MediaType, VideoType};
 use crate::video::{Video, VideoLoader};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use slint::{ComponentHandle, Image, Rgb8Pixel, SharedPixelBuffer, Weak};
 use std::path::Path;
 use std::sync::{Arc, Mutex, MutexGuard};
 
+/// Number of the previous and the next images decoded in advance
+const PREFETCH_RADIUS: usize = 2;
+
+/// Keeps the prefetched images and a few recently viewed ones
+const CACHE_CAPACITY: usize = 2 * PREFETCH_RADIUS + 3;
+
+/// One thread for the requested media and one per prefetched neighbour,
+/// so the viewer doesn't wait for the indexing jobs on the global pool
+const THREAD_COUNT: usize = 2 * PREFETCH_RADIUS + 1;
+
 #[derive(Clone)]
 pub struct MediaLoader {
+    pool: Arc<ThreadPool>,
     db: Arc<Mutex<IndexDb>>,
     video_loader: Arc<Mutex<Option<VideoLoader>>>,
     requested_idx: Arc<Mutex<Option<usize>>>,
+    cache: Arc<Mutex<ImageCache>>,
 }
 
 // Mutex lock wrappers
@@ -27,53 +40,90 @@ impl MediaLoader {
     fn requested_idx(&self) -> MutexGuard<'_, Option<usize>> {
         self.requested_idx.lock().unwrap()
     }
+
+    fn cache(&self) -> MutexGuard<'_, ImageCache> {
+        self.cache.lock().unwrap()
+    }
 }
 
 // Public API
 impl MediaLoader {
-    pub fn new(db: Arc<Mutex<IndexDb>>, video_loader: Arc<Mutex<Option<VideoLoader>>>) -> Self {
-        Self {
+    pub fn new(
+        db: Arc<Mutex<IndexDb>>,
+        video_loader: Arc<Mutex<Option<VideoLoader>>>,
+    ) -> anyhow::Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(THREAD_COUNT)
+            .thread_name(|idx| format!("media-loader-{idx}"))
+            .build()?;
+
+        Ok(Self {
+            pool: Arc::new(pool),
             db,
             video_loader,
             requested_idx: Default::default(),
-        }
+            cache: Arc::new(Mutex::new(ImageCache::new(CACHE_CAPACITY))),
+        })
     }
 
     pub fn cancel_loading(&self) {
         *self.requested_idx() = None;
     }
 
+    /// Drops the cached images, should be called when the media indices are changed
+    pub fn reset_cache(&self) {
+        self.cache().clear();
+    }
+
     pub fn load<TApp: ComponentHandle + 'static>(
         &self,
         idx: usize,
+        size: ImageSize,
         weak_app: Weak<TApp>,
-        on_start: impl FnOnce(TApp, &str),
+        on_start: impl FnOnce(TApp, &MediaEntry),
         on_finish: impl FnOnce(TApp, anyhow::Result<Media>) + Send + 'static,
     ) -> anyhow::Result<()> {
         let mut requested_idx = self.requested_idx();
+        let cached = self.cache().get(idx, size);
 
-        if requested_idx.is_some() {
+        // A cached image replaces the request that is in progress
+        if requested_idx.is_some() && cached.is_none() {
             return Err(anyhow::anyhow!("Loading is already in progress"));
         }
 
-        let (path, metadata_raw) = self.db().get_path_and_metadata(idx as i64)?;
+        let entry = self.db().get_entry(idx as i64)?;
         let app = weak_app
             .upgrade()
             .ok_or_else(|| anyhow::anyhow!("Failed to upgrade weak app"))?;
-        on_start(app, &path);
+
+        if let Some(buffer) = cached {
+            *requested_idx = None;
+            drop(requested_idx);
+
+            on_start(app.clone_strong(), &entry);
+            on_finish(app, Ok(Media::Image(Image::from_rgb8(buffer))));
+            self.prefetch(idx, size);
+            return Ok(());
+        }
+
+        on_start(app, &entry);
 
         *requested_idx = Some(idx);
-        rayon::spawn_fifo({
+        let generation = self.cache().generation();
+        self.pool.spawn_fifo({
             let loader = self.clone();
             move || {
-                let load_result = loader.load_inner(&path, metadata_raw);
+                let load_result = loader.load_inner(&entry, size);
+
+                // Keep the image even if the request is cancelled, it may be requested again
+                if let Ok(MediaInner::Image(buffer)) = &load_result {
+                    loader.cache().insert(generation, idx, size, buffer.clone());
+                }
 
                 let _ = weak_app.upgrade_in_event_loop(move |app| {
-                    let mut requested_idx = loader.requested_idx();
-                    if *requested_idx != Some(idx) {
+                    if !loader.finish_request(idx) {
                         return;
                     }
-                    *requested_idx = None;
 
                     let load_result = load_result.map(|m| match m {
                         MediaInner::Image(i) => Media::Image(Image::from_rgb8(i)),
@@ -81,6 +131,42 @@ impl MediaLoader {
                     });
 
                     on_finish(app, load_result);
+                    loader.prefetch(idx, size);
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Loads the motion part of the Live Photo or the motion photo at the index
+    pub fn load_motion_video<TApp: ComponentHandle + 'static>(
+        &self,
+        idx: usize,
+        weak_app: Weak<TApp>,
+        on_finish: impl FnOnce(TApp, anyhow::Result<Video>) + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let mut requested_idx = self.requested_idx();
+
+        if requested_idx.is_some() {
+            return Err(anyhow::anyhow!("Loading is already in progress"));
+        }
+
+        let entry = self.db().get_entry(idx as i64)?;
+        if !entry.has_motion() {
+            return Err(anyhow::anyhow!("No motion video"));
+        }
+
+        *requested_idx = Some(idx);
+        self.pool.spawn_fifo({
+            let loader = self.clone();
+            move || {
+                let load_result = loader.load_motion_video_inner(&entry);
+
+                let _ = weak_app.upgrade_in_event_loop(move |app| {
+                    if loader.finish_request(idx) {
+                        on_finish(app, load_result);
+                    }
                 });
             }
         });
@@ -96,34 +182,108 @@ pub enum MediaInner {
 }
 
 impl MediaLoader {
-    fn load_inner(&self, path: &str, metadata_raw: i64) -> anyhow::Result<MediaInner> {
-        let path = Path::new(path);
-        let media_type = MediaType::from_path(path).ok_or(anyhow::anyhow!("Invalid media type"))?;
-
-        match media_type {
-            MediaType::Image(img_type) => {
-                let orientation = ExifOrientation::try_from(metadata_raw).unwrap_or_default();
-                let img =
-                    image_loader::open(path, img_type).map(|img| img.oriented(orientation))?;
-
-                let rgb = img.into_rgb8();
-                let buf = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-                    rgb.as_raw(),
-                    rgb.width(),
-                    rgb.height(),
-                );
+    fn load_inner(&self, entry: &MediaEntry, size: ImageSize) -> anyhow::Result<MediaInner> {
+        let path = Path::new(&entry.path);
 
+        match entry.media_type {
+            MediaType::Image(_) => {
+                let buf = load_image(entry, size)?;
                 Ok(MediaInner::Image(buf))
             }
-            MediaType::Video(_video_type) => {
-                let video_loader = self.video_loader();
-                let video_loader = video_loader
-                    .as_ref()
-                    .ok_or_else(|| anyhow::anyhow!("Video loader is not initialized"))?;
-
-                let video = video_loader.load(path)?;
+            MediaType::Video(video_type) => {
+                let video = self.load_video(path, video_type)?;
                 Ok(MediaInner::Video(video))
             }
         }
     }
+
+    /// Decodes the neighbouring images in the background starting from the next one.
+    /// Videos are not prefetched
+    fn prefetch(&self, idx: usize, size: ImageSize) {
+        let neighbours = (1..=PREFETCH_RADIUS)
+            .flat_map(|offset| [idx.checked_add(offset), idx.checked_sub(offset)])
+            .flatten();
+
+        for idx in neighbours {
+            if !self.cache().start_prefetch(idx, size) {
+                continue;
+            }
+
+            let generation = self.cache().generation();
+            self.pool.spawn_fifo({
+                let loader = self.clone();
+                move || {
+                    loader.prefetch_inner(idx, size, generation);
+                    loader.cache().finish_prefetch(generation, idx);
+                }
+            });
+        }
+    }
+
+    fn prefetch_inner(&self, idx: usize, size: ImageSize, generation: u64) {
+        let entry = match self.db().get_entry(idx as i64) {
+            Ok(entry) if !entry.media_type.is_video() => entry,
+            _ => return,
+        };
+
+        match load_image(&entry, size) {
+            Ok(buffer) => self.cache().insert(generation, idx, size, buffer),
+            Err(e) => log::debug!("Failed to prefetch `{}`: {e}", entry.path),
+        }
+    }
+
+    fn load_video(&self, path: &Path, video_type: VideoType) -> anyhow::Result<Video> {
+        let video_loader = self.video_loader();
+        let video_loader = video_loader
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Video loader is not initialized"))?;
+
+        video_loader.load(path, video_type)
+    }
+
+    fn load_motion_video_inner(&self, entry: &MediaEntry) -> anyhow::Result<Video> {
+        // Only QuickTime videos are paired with stills
+        if let Some(live_video) = &entry.live_video {
+            return self.load_video(Path::new(live_video), VideoType::Mov);
+        }
+
+        let embedded = entry
+            .motion_video
+            .ok_or_else(|| anyhow::anyhow!("No motion video"))?;
+
+        let video_loader = self.video_loader();
+        let video_loader = video_loader
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Video loader is not initialized"))?;
+
+        video_loader.load_embedded(Path::new(&entry.path), embedded)
+    }
+
+    /// Returns `false` if the request is cancelled or replaced by another one
+    fn finish_request(&self, idx: usize) -> bool {
+        let mut requested_idx = self.requested_idx();
+        if *requested_idx != Some(idx) {
+            return false;
+        }
+
+        *requested_idx = None;
+        true
+    }
+}
+
+fn load_image(entry: &MediaEntry, size: ImageSize) -> anyhow::Result<SharedPixelBuffer<Rgb8Pixel>> {
+    let MediaType::Image(img_type) = entry.media_type else {
+        return Err(anyhow::anyhow!("Not an image"));
+    };
+
+    let orientation = entry.orientation.unwrap_or_default();
+    let img = image_loader::open_sized(&entry.path, img_type, size)
+        .map(|img| img.fit_oriented(size, orientation))?;
+
+    let rgb = img.into_rgb8();
+    Ok(SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
+        rgb.as_raw(),
+        rgb.width(),
+        rgb.height(),
+    ))
 }
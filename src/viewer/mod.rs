@@ -1,46 +1,220 @@
+mod grid_layout;
+mod image_cache;
 mod image_grid_model;
 mod media_loader;
 mod playing_video;
+mod scrubber_model;
+mod thumbnail_loader;
 
 use self::image_grid_model::ImageGridModel;
 use self::media_loader::MediaLoader;
 use self::playing_video::CurrentVideo;
-use crate::db::IndexDb;
-use crate::media::{Media, MediaType};
+use self::scrubber_model::ScrubberModel;
+use crate::config::GalleryConfig;
+use crate::db::{IndexDb, MediaEntry, SortOrder};
+use crate::image_loader::ImageSize;
+use crate::media::Media;
 use crate::ui::{MediaViewerBridge, MediaViewerModel, PhotoFlowApp, TimeFormatter, ViewerState};
 use crate::util;
-use crate::video::VideoLoader;
+use crate::video::{Video, VideoLoader};
 use anyhow::anyhow;
-use slint::{ComponentHandle, Image, RenderingState, Weak};
+use slint::{ComponentHandle, Image, ModelRc, RenderingState, SharedString, VecModel, Weak};
 use std::path::Path;
 use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 
-pub fn bind_gallery_models(app: &PhotoFlowApp, db: Arc<Mutex<IndexDb>>) -> anyhow::Result<()> {
+pub fn bind_gallery_models(
+    app: &PhotoFlowApp,
+    db: Arc<Mutex<IndexDb>>,
+    config: GalleryConfig,
+) -> anyhow::Result<()> {
+    let image_grid_model = Rc::new(ImageGridModel::new(db.clone(), app.as_weak(), config)?);
+    app.set_grid_model(image_grid_model.clone().into());
+    app.on_set_grid_visible_range({
+        let image_grid_model = image_grid_model.clone();
+        move |offset, len| {
+            image_grid_model.set_range(offset as usize, len as usize);
+        }
+    });
+    app.on_selection_changed({
+        let image_grid_model = image_grid_model.clone();
+        move |idx| {
+            image_grid_model.track_selection(idx as usize);
+        }
+    });
+    bind_grid_layout(app, image_grid_model);
+
+    let scrubber_model = Rc::new(ScrubberModel::new(db.clone()));
+    app.set_scrubber_months(scrubber_model.clone().into());
+    app.on_scrubber_month_of(move |idx| scrubber_model.month_of(idx.max(0) as usize) as i32);
+
     {
         let db = db.lock().map_err(|_| anyhow!("Failed to lock IndexDB"))?;
         let item_count = db.get_item_count()?;
-        app.invoke_set_item_count(item_count as i32);
+
+        // The newest media is selected first
+        let selected_idx = match db.get_sort_order()? {
+            SortOrder::OldestFirst => (item_count - 1).max(0),
+            _ => 0,
+        };
+        app.invoke_update_item_count(item_count as i32, selected_idx as i32);
     }
 
-    let image_grid_model = Rc::new(ImageGridModel::new(db.clone()));
-    app.set_grid_model(image_grid_model.clone().into());
-    app.on_set_grid_visible_range(move |offset, len| {
-        image_grid_model.set_range(offset as usize, len as usize);
+    Ok(())
+}
+
+fn bind_grid_layout(app: &PhotoFlowApp, image_grid_model: Rc<ImageGridModel>) {
+    app.on_grid_item_pos({
+        let image_grid_model = image_grid_model.clone();
+        move |idx, cols, _| {
+            let layout = image_grid_model.layout();
+            layout.item_pos(idx.max(0) as usize, cols.max(0) as usize)
+        }
+    });
+    app.on_grid_layout_size({
+        let image_grid_model = image_grid_model.clone();
+        move |cols, _| image_grid_model.layout().size(cols.max(0) as usize)
+    });
+    app.on_grid_index_at({
+        let image_grid_model = image_grid_model.clone();
+        move |y, cols, item_size, header_size, _| {
+            let layout = image_grid_model.layout();
+            layout.index_at(y, cols.max(0) as usize, item_size, header_size) as i32
+        }
+    });
+    app.on_grid_row_neighbour({
+        let image_grid_model = image_grid_model.clone();
+        move |idx, cols, delta, _| {
+            let layout = image_grid_model.layout();
+            layout.row_neighbour(idx.max(0) as usize, cols.max(0) as usize, delta as isize) as i32
+        }
+    });
+    app.on_grid_group_start(move |idx, delta, _| {
+        let layout = image_grid_model.layout();
+        layout.group_start(idx.max(0) as usize, delta as isize) as i32
+    });
+}
+
+/// The order is stored through a separate connection owned by a dedicated thread, because
+/// the gallery reads the index with a read-only one and the indexer owns the other writable one
+pub fn bind_sort_menu(
+    app: &PhotoFlowApp,
+    db: &Mutex<IndexDb>,
+    sort_db: IndexDb,
+) -> anyhow::Result<()> {
+    let labels = SortOrder::ALL
+        .iter()
+        .map(|order| SharedString::from(sort_order_label(*order)))
+        .collect::<Vec<_>>();
+    app.set_sort_orders(ModelRc::new(VecModel::from(labels)));
+
+    let order = db
+        .lock()
+        .map_err(|_| anyhow!("Failed to lock IndexDB"))
+        .and_then(|db| Ok(db.get_sort_order()?))
+        .inspect_err(|e| log::error!("Failed to get sort order: {e}"))
+        .unwrap_or_default();
+    app.set_sort_order(sort_order_idx(order));
+
+    let (sender, receiver) = mpsc::channel();
+    let weak_app = app.as_weak();
+    thread::Builder::new()
+        .name("sort-order-writer".into())
+        .spawn(move || write_sort_orders(sort_db, receiver, weak_app))?;
+
+    let weak_app = app.as_weak();
+    app.on_sort_order_selected(move |idx| {
+        let Some(order) = SortOrder::ALL.get(idx as usize).copied() else {
+            return;
+        };
+
+        if let Some(app) = weak_app.upgrade() {
+            app.set_sort_order(idx);
+        }
+
+        if sender.send(order).is_err() {
+            log::error!("Failed to change sort order: the writer thread is stopped");
+        }
     });
 
     Ok(())
 }
 
-pub fn bind_media_viewer(app: &PhotoFlowApp, db: Arc<Mutex<IndexDb>>) {
+/// Stores the selected orders until the menu is dropped and refreshes the gallery
+/// with the stored one. Orders selected while the previous one was stored are skipped
+/// except the last one
+fn write_sort_orders(mut db: IndexDb, receiver: Receiver<SortOrder>, weak_app: Weak<PhotoFlowApp>) {
+    while let Ok(order) = receiver.recv() {
+        let order = receiver.try_iter().last().unwrap_or(order);
+        if let Err(e) = db.set_sort_order(order) {
+            log::error!("Failed to change sort order: {e}");
+        }
+
+        let stored_order = match db.get_sort_order() {
+            Ok(order) => order,
+            Err(e) => {
+                log::error!("Failed to get sort order: {e}");
+                continue;
+            }
+        };
+
+        let _ = weak_app.upgrade_in_event_loop(move |app| {
+            app.set_sort_order(sort_order_idx(stored_order));
+            if let Err(e) = refresh_gallery_models(&app) {
+                log::error!("Failed to refresh gallery models: {e}");
+            }
+            app.invoke_show_selected();
+        });
+    }
+}
+
+fn sort_order_idx(order: SortOrder) -> i32 {
+    let idx = SortOrder::ALL.iter().position(|o| *o == order);
+    idx.unwrap_or_default() as i32
+}
+
+fn sort_order_label(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::NewestFirst => "Newest first",
+        SortOrder::OldestFirst => "Oldest first",
+        SortOrder::FileName => "File name",
+        SortOrder::SourceThenDate => "Source, then date",
+        SortOrder::FileModified => "Recently modified",
+    }
+}
+
+/// Updates the bound gallery models after the index is rebuilt keeping the selected media
+pub fn refresh_gallery_models(app: &PhotoFlowApp) -> anyhow::Result<()> {
+    let grid_model = app.get_grid_model();
+    let image_grid_model = grid_model
+        .as_any()
+        .downcast_ref::<ImageGridModel>()
+        .ok_or_else(|| anyhow!("Gallery models are not bound"))?;
+
+    let (count, selected_idx) = image_grid_model.reload(app.get_selected_idx() as usize)?;
+    app.set_grid_layout_version(app.get_grid_layout_version() + 1);
+
+    let scrubber_months = app.get_scrubber_months();
+    if let Some(scrubber_model) = scrubber_months.as_any().downcast_ref::<ScrubberModel>() {
+        scrubber_model.reload()?;
+    }
+    app.invoke_update_item_count(count as i32, selected_idx as i32);
+    app.global::<MediaViewerBridge>().invoke_reset_cache();
+
+    Ok(())
+}
+
+pub fn bind_media_viewer(app: &PhotoFlowApp, db: Arc<Mutex<IndexDb>>) -> anyhow::Result<()> {
     let formatter = app.global::<TimeFormatter>();
     formatter.on_hh_mm_ss(move |duration_ms| util::hh_mm_ss(duration_ms as u64).into());
 
     let bridge = app.global::<MediaViewerBridge>();
 
     let video_loader = Arc::new(Mutex::new(None));
-    let loader = MediaLoader::new(db, video_loader.clone());
+    let loader = MediaLoader::new(db, video_loader.clone())?;
     let curr_video = CurrentVideo::default();
 
     bridge.on_load({
@@ -63,6 +237,13 @@ pub fn bind_media_viewer(app: &PhotoFlowApp, db: Arc<Mutex<IndexDb>>) {
         }
     });
 
+    bridge.on_reset_cache({
+        let loader = loader.clone();
+        move || {
+            loader.reset_cache();
+        }
+    });
+
     bridge.on_poll_video_state({
         let app_weak = app.as_weak();
         let curr_video = curr_video.clone();
@@ -85,6 +266,25 @@ pub fn bind_media_viewer(app: &PhotoFlowApp, db: Arc<Mutex<IndexDb>>) {
         }
     });
 
+    bridge.on_play_live({
+        let app_weak = app.as_weak();
+        let loader = loader.clone();
+        let curr_video = curr_video.clone();
+        move |idx| {
+            if let Err(e) = play_live(&app_weak, &loader, idx as usize, &curr_video) {
+                log::error!("Failed to play motion video ({idx}): {e}");
+            }
+        }
+    });
+
+    bridge.on_stop_live({
+        let app_weak = app.as_weak();
+        let curr_video = curr_video.clone();
+        move || {
+            stop_live(&app_weak, &curr_video);
+        }
+    });
+
     app.window()
         .set_rendering_notifier({
             let app_weak = app.as_weak();
@@ -113,6 +313,8 @@ pub fn bind_media_viewer(app: &PhotoFlowApp, db: Arc<Mutex<IndexDb>>) {
             }
         })
         .ok();
+
+    Ok(())
 }
 
 fn load(
@@ -121,13 +323,18 @@ fn load(
     idx: usize,
     curr_video: &CurrentVideo,
 ) -> anyhow::Result<()> {
+    let size = weak_app
+        .upgrade()
+        .map_or(ImageSize::Full, |app| viewer_image_size(&app));
+
     loader.load(
         idx,
+        size,
         weak_app.clone(),
         {
             let playing_video = curr_video.clone();
-            move |app, path| {
-                on_load_start(app, path, playing_video);
+            move |app, entry| {
+                on_load_start(app, entry, playing_video);
             }
         },
         {
@@ -139,11 +346,22 @@ fn load(
     )
 }
 
-fn on_load_start(app: PhotoFlowApp, path: &str, curr_video: CurrentVideo) {
-    let file_name = Path::new(path)
+/// Images are decoded at the physical window size, the full resolution is used
+/// only if the size is unknown yet
+fn viewer_image_size(app: &PhotoFlowApp) -> ImageSize {
+    let size = app.window().size();
+    if size.width == 0 || size.height == 0 {
+        return ImageSize::Full;
+    }
+
+    ImageSize::Fit(size.width, size.height)
+}
+
+fn on_load_start(app: PhotoFlowApp, entry: &MediaEntry, curr_video: CurrentVideo) {
+    let file_name = Path::new(&entry.path)
         .file_name()
         .and_then(|n| n.to_str())
-        .unwrap_or(path);
+        .unwrap_or(&entry.path);
 
     let bridge = app.global::<MediaViewerBridge>();
     let model = bridge.get_model();
@@ -153,18 +371,12 @@ fn on_load_start(app: PhotoFlowApp, path: &str, curr_video: CurrentVideo) {
         image = frame;
     }
 
-    let is_video = MediaType::from_path(path)
-        .map(|mt| match mt {
-            MediaType::Image(_) => false,
-            MediaType::Video(_) => true,
-        })
-        .unwrap_or(false);
-
     bridge.set_model(MediaViewerModel {
         state: ViewerState::Loading,
         file_name: file_name.into(),
         image,
-        is_video,
+        is_video: entry.media_type.is_video(),
+        is_live: entry.has_motion(),
         ..Default::default()
     });
 }
@@ -201,6 +413,68 @@ fn on_load_finish(app: PhotoFlowApp, curr_video: CurrentVideo, result: anyhow::R
     bridge.set_model(model);
 }
 
+fn play_live(
+    weak_app: &Weak<PhotoFlowApp>,
+    loader: &MediaLoader,
+    idx: usize,
+    curr_video: &CurrentVideo,
+) -> anyhow::Result<()> {
+    loader.load_motion_video(idx, weak_app.clone(), {
+        let weak_app = weak_app.clone();
+        let curr_video = curr_video.clone();
+        move |app, result| {
+            on_live_load_finish(app, weak_app, curr_video, result);
+        }
+    })
+}
+
+/// Plays the motion part once and then returns to the still
+fn on_live_load_finish(
+    app: PhotoFlowApp,
+    weak_app: Weak<PhotoFlowApp>,
+    curr_video: CurrentVideo,
+    result: anyhow::Result<Video>,
+) {
+    let video = match result {
+        Ok(video) => video,
+        Err(e) => {
+            log::error!("Failed to load motion video: {e}");
+            return;
+        }
+    };
+
+    video.set_on_eos({
+        let curr_video = curr_video.clone();
+        move || {
+            stop_live(&weak_app, &curr_video);
+        }
+    });
+    curr_video.set(video);
+    curr_video.set_playing(true);
+
+    let bridge = app.global::<MediaViewerBridge>();
+    let model = bridge.get_model();
+    bridge.set_model(MediaViewerModel {
+        live_playing: true,
+        ..model
+    });
+}
+
+fn stop_live(weak_app: &Weak<PhotoFlowApp>, curr_video: &CurrentVideo) -> Option<()> {
+    curr_video.stop();
+
+    let app = weak_app.upgrade()?;
+    let bridge = app.global::<MediaViewerBridge>();
+    let model = bridge.get_model();
+    bridge.set_model(MediaViewerModel {
+        live_playing: false,
+        live_frame: Image::default(),
+        ..model
+    });
+
+    Some(())
+}
+
 fn clear(
     weak_app: &Weak<PhotoFlowApp>,
     loader: &MediaLoader,
@@ -220,7 +494,15 @@ fn try_set_bridge_image(app_weak: &Weak<PhotoFlowApp>, image: Image) {
     if let Some(app) = app_weak.upgrade() {
         let bridge = app.global::<MediaViewerBridge>();
         let model = bridge.get_model();
-        bridge.set_model(MediaViewerModel { image, ..model });
+        let model = if model.live_playing {
+            MediaViewerModel {
+                live_frame: image,
+                ..model
+            }
+        } else {
+            MediaViewerModel { image, ..model }
+        };
+        bridge.set_model(model);
     }
 }
 
@@ -0,0 +1,105 @@
+use crate::config::TimelineGrouping;
+use crate::db::{GroupKey, IndexDb, MediaGroup};
+use crate::ui::ScrubberMonth;
+use anyhow::anyhow;
+use chrono::Datelike;
+use slint::{Model, ModelNotify, ModelTracker};
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+/// Months of the gallery items shown on the timeline scrubber ruler.
+/// The file name order shows the first letters instead
+pub struct ScrubberModel {
+    db: Arc<Mutex<IndexDb>>,
+    months: RefCell<Vec<MediaGroup>>,
+    notify: ModelNotify,
+}
+
+impl ScrubberModel {
+    pub fn new(db: Arc<Mutex<IndexDb>>) -> Self {
+        let months = load_months(&db)
+            .inspect_err(|e| log::error!("Failed to load timeline months: {e}"))
+            .unwrap_or_default();
+
+        Self {
+            db,
+            months: RefCell::new(months),
+            notify: Default::default(),
+        }
+    }
+
+    pub fn reload(&self) -> anyhow::Result<()> {
+        *self.months.borrow_mut() = load_months(&self.db)?;
+        self.notify.reset();
+        Ok(())
+    }
+
+    /// Returns the month row that contains the media
+    pub fn month_of(&self, idx: usize) -> usize {
+        let months = self.months.borrow();
+        months
+            .partition_point(|month| month.offset <= idx)
+            .saturating_sub(1)
+    }
+}
+
+impl Model for ScrubberModel {
+    type Data = ScrubberMonth;
+
+    fn row_count(&self) -> usize {
+        self.months.borrow().len()
+    }
+
+    fn row_data(&self, row: usize) -> Option<Self::Data> {
+        let months = self.months.borrow();
+        let month = months.get(row)?;
+
+        let mark = year_mark(&month.key);
+        let is_year_start = mark.is_some()
+            && row
+                .checked_sub(1)
+                .and_then(|prev_row| months.get(prev_row))
+                .is_none_or(|prev| year_mark(&prev.key) != mark);
+
+        let (month_label, year) = match &month.key {
+            GroupKey::Date(date) => (date.format("%B").to_string(), date.year().to_string()),
+            GroupKey::SourceDate(source, date) => (
+                format!("{} · {source}", date.format("%B")),
+                date.year().to_string(),
+            ),
+            GroupKey::Letter(letter) => (letter.clone(), String::new()),
+        };
+
+        Some(ScrubberMonth {
+            index: month.offset as i32,
+            count: month.count as i32,
+            month: month_label.into(),
+            year: year.into(),
+            is_year_start,
+        })
+    }
+
+    fn model_tracker(&self) -> &dyn ModelTracker {
+        &self.notify
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The year label is shown again when the year or the source is changed
+fn year_mark(key: &GroupKey) -> Option<(&str, i32)> {
+    match key {
+        GroupKey::Date(date) => Some(("", date.year())),
+        GroupKey::SourceDate(source, date) => Some((source, date.year())),
+        GroupKey::Letter(_) => None,
+    }
+}
+
+fn load_months(db: &Mutex<IndexDb>) -> anyhow::Result<Vec<MediaGroup>> {
+    let db = db.lock().map_err(|_| anyhow!("Failed to lock DB"))?;
+    let months = db.get_groups(TimelineGrouping::Month)?;
+    Ok(months)
+}
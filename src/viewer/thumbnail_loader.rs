@@ -0,0 +1,153 @@
+use super::image_grid_model::ImageGridModel;
+use crate::db::IndexDb;
+use crate::ui::PhotoFlowApp;
+use crate::util;
+use anyhow::anyhow;
+use image::codecs::jpeg::JpegDecoder;
+use image::{ColorType, ImageDecoder};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use slint::{Rgb8Pixel, SharedPixelBuffer, SharedString, Weak};
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Threads decoding the thumbnails, the global pool is left to the viewer media and the indexer
+const THREAD_COUNT: usize = 2;
+
+/// Fetches and decodes thumbnails on its own pool and passes them to the grid model
+pub struct ThumbnailLoader {
+    pool: ThreadPool,
+    db: Arc<Mutex<IndexDb>>,
+    weak_app: Weak<PhotoFlowApp>,
+    wanted: Arc<Mutex<WantedSet>>,
+}
+
+/// Rows waiting for their thumbnails. Workers skip the rows that are scrolled away,
+/// and the generation is changed when the indices become invalid
+#[derive(Default)]
+struct WantedSet {
+    rows: HashSet<usize>,
+    generation: u64,
+}
+
+/// Thumbnail data that can be sent between threads
+pub struct LoadedThumbnail {
+    pub image: SharedPixelBuffer<Rgb8Pixel>,
+    pub video_duration: Option<SharedString>,
+    pub is_live: bool,
+}
+
+impl ThumbnailLoader {
+    pub fn new(db: Arc<Mutex<IndexDb>>, weak_app: Weak<PhotoFlowApp>) -> anyhow::Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(THREAD_COUNT)
+            .thread_name(|idx| format!("thumbnail-loader-{idx}"))
+            .build()?;
+
+        Ok(Self {
+            pool,
+            db,
+            weak_app,
+            wanted: Default::default(),
+        })
+    }
+
+    fn wanted(&self) -> MutexGuard<'_, WantedSet> {
+        self.wanted.lock().unwrap()
+    }
+
+    pub fn request(&self, db_idx: usize) {
+        let generation = {
+            let mut wanted = self.wanted();
+            if !wanted.rows.insert(db_idx) {
+                return;
+            }
+            wanted.generation
+        };
+
+        let db = self.db.clone();
+        let weak_app = self.weak_app.clone();
+        let wanted = self.wanted.clone();
+
+        self.pool.spawn_fifo(move || {
+            let is_wanted = || wanted.lock().unwrap().is_wanted(db_idx, generation);
+            if !is_wanted() {
+                return;
+            }
+
+            let thumbnail = load_thumbnail(&db, db_idx)
+                .inspect_err(|e| log::error!("Failed to get thumbnail ({db_idx}): {e}"))
+                .ok();
+
+            if !is_wanted() {
+                return;
+            }
+
+            let _ = weak_app.upgrade_in_event_loop(move |app| {
+                let grid_model = app.get_grid_model();
+                if let Some(model) = grid_model.as_any().downcast_ref::<ImageGridModel>() {
+                    match thumbnail {
+                        Some(thumbnail) => model.set_thumbnail(db_idx, generation, thumbnail),
+                        None => model.reset_thumbnail(db_idx, generation),
+                    }
+                }
+            });
+        });
+    }
+
+    pub fn cancel(&self, db_idx: usize) {
+        self.wanted().rows.remove(&db_idx);
+    }
+
+    /// Cancels all requests, the thumbnails loaded before are not accepted anymore
+    pub fn reset(&self) {
+        let mut wanted = self.wanted();
+        wanted.rows.clear();
+        wanted.generation += 1;
+    }
+
+    /// Returns `true` if the thumbnail (or its loading error) is still wanted and stops waiting for it
+    pub fn accept(&self, db_idx: usize, generation: u64) -> bool {
+        self.wanted().remove(db_idx, generation)
+    }
+}
+
+impl WantedSet {
+    fn is_wanted(&self, db_idx: usize, generation: u64) -> bool {
+        self.generation == generation && self.rows.contains(&db_idx)
+    }
+
+    fn remove(&mut self, db_idx: usize, generation: u64) -> bool {
+        self.generation == generation && self.rows.remove(&db_idx)
+    }
+}
+
+fn load_thumbnail(db: &Mutex<IndexDb>, db_idx: usize) -> anyhow::Result<LoadedThumbnail> {
+    let (entry, thumbnail) = {
+        let db = db.lock().map_err(|_| anyhow!("Failed to lock DB"))?;
+        (
+            db.get_entry(db_idx as i64)?,
+            db.get_thumbnail(db_idx as i64)?,
+        )
+    };
+
+    let decoder = JpegDecoder::new(Cursor::new(thumbnail))?;
+    if decoder.color_type() != ColorType::Rgb8 {
+        return Err(anyhow!("Unexpected thumbnail color type"));
+    }
+
+    let (w, h) = decoder.dimensions();
+    let mut image = SharedPixelBuffer::<Rgb8Pixel>::new(w, h);
+    decoder.read_image(image.make_mut_bytes())?;
+
+    let video_duration = entry
+        .duration_ms
+        .filter(|_| entry.media_type.is_video())
+        .map(|duration_ms| util::hh_mm_ss(duration_ms).into());
+
+    Ok(LoadedThumbnail {
+        image,
+        video_duration,
+        is_live: entry.has_motion(),
+    })
+}